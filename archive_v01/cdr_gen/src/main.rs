@@ -89,15 +89,17 @@ fn calculate_luhn_checksum(number: &str) -> u8 {
         .rev()
         .enumerate()
         .map(|(i, &d)| {
+            // The check digit is appended on the right, so the rightmost
+            // digit of the payload is the first to be doubled.
             if i % 2 == 0 {
-                d
-            } else {
                 let doubled = d * 2;
                 if doubled > 9 {
                     doubled - 9
                 } else {
                     doubled
                 }
+            } else {
+                d
             }
         })
         .sum();
@@ -1,4 +1,5 @@
-use sled::{Db, IVec};
+use repository::Repository;
+use sled::Db;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,14 +21,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ("5G", "Fifth generation mobile telecommunications"),
     ];
 
-    // Define the technology prefix
-    let technology_prefix = "technology_";
+    // Technologies are kept in their own tree, keyed by id
+    let technologies: Repository<u8, NetworkTechnology> = Repository::open(&db, "technology")?;
 
     // Insert technologies into the database
-    insert_technologies(&db, &technologies_data, technology_prefix)?;
+    insert_technologies(&technologies, &technologies_data)?;
+    db.flush()?;
 
     // Retrieve and print all stored technologies
-    let stored_technologies = get_all_technologies(&db, technology_prefix)?;
+    let stored_technologies = get_all_technologies(&technologies)?;
     for tech in stored_technologies {
         println!("{:?}", tech);
     }
@@ -36,7 +38,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 /// Inserts a list of technologies into the Sled database.
-fn insert_technologies(db: &Db, technologies_data: &[(&str, &str)], prefix: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn insert_technologies(technologies: &Repository<u8, NetworkTechnology>, technologies_data: &[(&str, &str)]) -> Result<(), Box<dyn std::error::Error>> {
     for (index, &(name, description)) in technologies_data.iter().enumerate() {
         let technology = NetworkTechnology {
             id: (index + 1) as u8, // IDs start from 1
@@ -44,34 +46,25 @@ fn insert_technologies(db: &Db, technologies_data: &[(&str, &str)], prefix: &str
             description: description.to_string(),
         };
 
-        insert_technology(db, technology, prefix)?;
+        insert_technology(technologies, technology)?;
     }
-    db.flush()?;
     Ok(())
 }
 
 /// Inserts a single technology into the Sled database.
-fn insert_technology(db: &Db, technology: NetworkTechnology, prefix: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let key = format!("{}{}", prefix, technology.id); // Create a key with prefix
-    let serialized_tech = bincode::serialize(&technology)?; // Serialize the technology
-
-    // Insert into the database with the prefixed key
-    db.insert(key, serialized_tech)?;
+fn insert_technology(technologies: &Repository<u8, NetworkTechnology>, technology: NetworkTechnology) -> Result<(), Box<dyn std::error::Error>> {
+    technologies.put(&technology.id, &technology)?; // Keyed by id, big-endian so they sort
     Ok(())
 }
 
 /// Returns a vector of all stored technologies from the Sled database.
-fn get_all_technologies(db: &Db, prefix: &str) -> Result<Vec<NetworkTechnology>, Box<dyn std::error::Error>> {
-    let mut technologies = Vec::new();
+fn get_all_technologies(technologies: &Repository<u8, NetworkTechnology>) -> Result<Vec<NetworkTechnology>, Box<dyn std::error::Error>> {
+    let mut stored = Vec::new();
 
-    for technology in db.iter() {
-        let (key, value) = technology?;
-        let tech_key = String::from_utf8(key.to_vec())?;
-        if tech_key.starts_with(prefix) { // Filter keys that start with the prefix
-            let tech: NetworkTechnology = bincode::deserialize(&value)?; // Deserialize the value
-            technologies.push(tech); // Collect the technology
-        }
+    for technology in technologies.iter() {
+        let (_, tech) = technology?;
+        stored.push(tech); // Collect the technology
     }
 
-    Ok(technologies) // Return the vector of technologies
+    Ok(stored) // Return the vector of technologies
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
-use sled::{Db, Tree};
+use repository::{Repository, RepositoryError};
+use sled::Db;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use bincode;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkTechnology {
@@ -12,52 +12,29 @@ pub struct NetworkTechnology {
 
 pub struct NetworkTechnologyRepository {
     db: Db,
+    technologies: Repository<u8, NetworkTechnology>,
 }
 
 impl NetworkTechnologyRepository {
-    pub fn new(db: Db) -> Self {
-        Self { db }
+    pub fn new(db: Db) -> Result<Self, RepositoryError> {
+        let technologies = Repository::open(&db, "technologies")?
+            .with_index("name", |tech: &NetworkTechnology| tech.name.clone())?;
+        Ok(Self { db, technologies })
     }
 
-    pub fn create(&self, tech: NetworkTechnology) -> Result<(), sled::Error> {
-        let key = tech.id.to_string();
-        let value = bincode::serialize(&tech)?;
-        self.db.insert(key, value)?;
+    pub fn create(&self, tech: NetworkTechnology) -> Result<(), RepositoryError> {
+        self.technologies.put(&tech.id, &tech)?;
         self.db.flush()?;
         Ok(())
     }
 
-    pub fn read_by_name(&self, name: &str) -> Result<Vec<NetworkTechnology>, sled::Error> {
-        let mut results = Vec::new();
-        for item in self.db.iter() {
-            let (_, value) = item?;
-            let tech: NetworkTechnology = bincode::deserialize(&value)?;
-            if tech.name == name {
-                results.push(tech);
-            }
-        }
-        Ok(results)
+    pub fn read_by_name(&self, name: &str) -> Result<Vec<NetworkTechnology>, RepositoryError> {
+        let found = self.technologies.find_by("name", &name.to_string())?;
+        Ok(found.into_iter().map(|(_, tech)| tech).collect())
     }
 
-
-    pub fn read_all(&self) -> Result<HashMap<u8, NetworkTechnology>, sled::Error> {
-        let mut all_techs: HashMap<u8, NetworkTechnology> = HashMap::new();
-        let mut iterator = self.db.tree().roots().next().unwrap().scan(0, |state, record| {
-            if record.is_some() {
-                *state += 1;
-                if *state % 1000 == 0 { 
-                    drop(record); 
-                    return Some(Ok(0));
-                }
-            }
-            record
-        })?;
-
-        for (key, value) in iterator {
-            let tech: NetworkTechnology = bincode::deserialize(&value)?;
-            all_techs.insert(tech.id, tech);
-        }
-        Ok(all_techs)
+    pub fn read_all(&self) -> Result<HashMap<u8, NetworkTechnology>, RepositoryError> {
+        self.technologies.iter().collect()
     }
 }
 
@@ -93,9 +70,9 @@ impl NetworkTechnologyService {
     }
 }
 
-fn main() -> Result<(), sled::Error> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let db = sled::open("my_db")?;
-    let repository = NetworkTechnologyRepository::new(db);
+    let repository = NetworkTechnologyRepository::new(db)?;
     let service = NetworkTechnologyService::new(repository);
 
     // Add technologies using the service
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use repository::{Repository, RepositoryError};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
@@ -8,7 +8,7 @@ pub struct NetworkTechnologyDTO {
     pub description: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NetworkTechnology {
     pub id: u32,
     pub name: String,
@@ -30,38 +30,51 @@ impl NetworkTechnology {
 
 
 pub struct NetworkTechnologyRepository {
-    technologies: Vec<NetworkTechnology>,
+    technologies: Repository<u32, NetworkTechnology>,
     next_id: u32,
-    existing_names: HashSet<String>, // For quick name uniqueness checks
 }
 
 impl NetworkTechnologyRepository {
-    pub fn new() -> Self {
-        Self {
-            technologies: Vec::new(),
-            next_id: 1,
-            existing_names: HashSet::new(),
-        }
+    pub fn new(db: &sled::Db) -> Result<Self, RepositoryError> {
+        // Names are indexed for quick uniqueness checks
+        let technologies = Repository::open(db, "technologies")?
+            .with_index("name", |tech: &NetworkTechnology| tech.name.clone())?;
+        let next_id = match technologies.last()? {
+            Some((id, _)) => id + 1,
+            None => 1,
+        };
+        Ok(Self {
+            technologies,
+            next_id,
+        })
     }
 
-    // Accepts the DTO, adds an ID, and then pushes it into the repository.
+    // Accepts the DTO, adds an ID, and then stores it in the repository.
     pub fn add_from_dto(&mut self, dto: NetworkTechnologyDTO) -> Result<(), String> {
-        if self.existing_names.contains(&dto.name) {
+        let existing = self
+            .technologies
+            .find_by("name", &dto.name)
+            .map_err(|e| e.to_string())?;
+        if !existing.is_empty() {
             return Err("A technology with the same name already exists".to_string());
         }
 
         // Create a new NetworkTechnology with a generated ID
         let technology = NetworkTechnology::new(self.next_id, &dto.name, &dto.description);
-        
-        self.technologies.push(technology);
-        self.existing_names.insert(dto.name);
+
+        self.technologies
+            .put(&technology.id, &technology)
+            .map_err(|e| e.to_string())?;
         self.next_id += 1; // Increment ID for the next entry
-        
+
         Ok(())
     }
 
-    pub fn list_all(&self) -> &[NetworkTechnology] {
-        &self.technologies
+    pub fn list_all(&self) -> Result<Vec<NetworkTechnology>, String> {
+        self.technologies
+            .iter()
+            .map(|item| item.map(|(_, tech)| tech).map_err(|e| e.to_string()))
+            .collect()
     }
 }
 
@@ -78,7 +91,7 @@ impl NetworkTechnologyService {
         self.repository.add_from_dto(dto)
     }
 
-    pub fn list_technologies(&self) -> &[NetworkTechnology] {
+    pub fn list_technologies(&self) -> Result<Vec<NetworkTechnology>, String> {
         self.repository.list_all()
     }
 }
@@ -86,7 +99,8 @@ impl NetworkTechnologyService {
 
 
 fn main() {
-    let repository = NetworkTechnologyRepository::new();
+    let db = sled::open("my_db").expect("Failed to open database");
+    let repository = NetworkTechnologyRepository::new(&db).expect("Failed to open repository");
     let mut service = NetworkTechnologyService::new(repository);
 
     // Example JSON data
@@ -111,7 +125,7 @@ fn main() {
 
     // List all technologies
     println!("All Technologies:");
-    for tech in service.list_technologies() {
+    for tech in service.list_technologies().unwrap() {
         println!("ID: {}, Name: {}, Description: {}", tech.id, tech.name, tech.description);
     }
 }
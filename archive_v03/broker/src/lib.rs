@@ -0,0 +1,703 @@
+// src/lib.rs
+pub use codec::{Codec, CodecError};
+
+use arrow::record_batch::RecordBatch;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::transaction::TransactionError;
+use sled::{Db, Transactional, Tree};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{convert::TryInto, sync::Arc};
+use tokio::sync::{Mutex, Notify};
+
+const TOPIC_PREFIX: &str = "topic:";
+const OFFSETS_PREFIX: &str = "offsets:";
+const DEAD_LETTERS_PREFIX: &str = "dead_letters:";
+const META_TREE: &str = "topic_meta";
+/// Key prefix, in `topic_meta`, of the offset the next message of a topic gets.
+const NEXT_OFFSET_PREFIX: &str = "next_offset:";
+const TIMESTAMP_LEN: usize = 8;
+const BLOCK_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How much history a topic keeps, independently of consumer progress.
+#[derive(Debug, Clone, Default)]
+pub struct Retention {
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Retention {
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+/// What `publish` does when a topic is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait until consumer groups commit and the consumed messages can be released.
+    #[default]
+    Block,
+    DropOldest,
+    DropNewest,
+}
+
+/// Upper bounds on what a topic keeps on disk.
+#[derive(Debug, Clone, Default)]
+pub struct Capacity {
+    pub max_items: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl Capacity {
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn allows(&self, items: usize, bytes: usize) -> bool {
+        self.max_items.is_none_or(|max| items <= max)
+            && self.max_bytes.is_none_or(|max| bytes <= max)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message<T = Value> {
+    pub offset: u64,
+    pub timestamp: u64,
+    pub value: T,
+}
+
+impl<T> Message<T> {
+    fn decode(
+        key: &[u8],
+        record: &[u8],
+        decode: impl FnOnce(&[u8]) -> sled::Result<T>,
+    ) -> sled::Result<Self> {
+        Ok(Message {
+            offset: decode_offset(key),
+            timestamp: decode_timestamp(record),
+            value: decode(&record[TIMESTAMP_LEN..])?,
+        })
+    }
+}
+
+/// A message a consumer group gave up on, kept aside with the reason.
+///
+/// The payload is kept exactly as published, in the topic's codec.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub group: String,
+    pub reason: String,
+    pub offset: u64,
+    pub timestamp: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Several named topics sharing one sled database.
+pub struct Broker {
+    db: Db,
+    meta: Tree,
+    topics: std::sync::Mutex<HashMap<String, Topic>>,
+}
+
+impl Broker {
+    pub fn open(path: &str) -> sled::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            meta: db.open_tree(META_TREE)?,
+            db,
+            topics: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Opens `name` with the codec it was created with, or JSON for a new topic.
+    pub fn topic(&self, name: &str) -> sled::Result<Topic> {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(topic) = topics.get(name) {
+            return Ok(topic.clone());
+        }
+        let codec = self.recorded_codec(name)?.unwrap_or_default();
+        let topic = self.open_topic(name, codec, Capacity::default())?;
+        topics.insert(name.to_string(), topic.clone());
+        Ok(topic)
+    }
+
+    /// Opens `name` only if it exists, without recording anything about it,
+    /// so that inspecting a database leaves it unchanged.
+    pub fn existing_topic(&self, name: &str) -> sled::Result<Option<Topic>> {
+        if let Some(topic) = self.topics.lock().unwrap().get(name) {
+            return Ok(Some(topic.clone()));
+        }
+        if !self.topic_names().iter().any(|topic| topic == name) {
+            return Ok(None);
+        }
+        let codec = self.recorded_codec(name)?.unwrap_or_default();
+        Topic::open(&self.db, name, codec, Capacity::default()).map(Some)
+    }
+
+    /// Opens `name` expecting `codec`, failing if the topic was created with another one.
+    pub fn topic_with_codec(&self, name: &str, codec: Codec) -> sled::Result<Topic> {
+        if let Some(recorded) = self.recorded_codec(name)? {
+            if recorded != codec {
+                return Err(sled::Error::Unsupported(format!(
+                    "topic {} is encoded with {}, not {}",
+                    name, recorded, codec
+                )));
+            }
+        }
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(topic) = topics.get(name) {
+            return Ok(topic.clone());
+        }
+        let topic = self.open_topic(name, codec, Capacity::default())?;
+        topics.insert(name.to_string(), topic.clone());
+        Ok(topic)
+    }
+
+    /// Opens `name` bounded by `capacity`. Handles obtained earlier keep their old bounds.
+    pub fn topic_with_capacity(&self, name: &str, capacity: Capacity) -> sled::Result<Topic> {
+        let mut topics = self.topics.lock().unwrap();
+        let topic = match topics.get(name) {
+            Some(topic) => Topic {
+                capacity,
+                ..topic.clone()
+            },
+            None => {
+                let codec = self.recorded_codec(name)?.unwrap_or_default();
+                self.open_topic(name, codec, capacity)?
+            }
+        };
+        topics.insert(name.to_string(), topic.clone());
+        Ok(topic)
+    }
+
+    fn open_topic(&self, name: &str, codec: Codec, capacity: Capacity) -> sled::Result<Topic> {
+        self.meta.insert(name, codec.name())?;
+        Topic::open(&self.db, name, codec, capacity)
+    }
+
+    fn recorded_codec(&self, name: &str) -> sled::Result<Option<Codec>> {
+        match self.meta.get(name)? {
+            Some(value) => String::from_utf8_lossy(&value)
+                .parse()
+                .map(Some)
+                .map_err(sled::Error::Unsupported),
+            None => Ok(None),
+        }
+    }
+
+    /// The underlying database, for stores kept next to the topics.
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    pub fn topic_names(&self) -> Vec<String> {
+        self.tree_names()
+            .into_iter()
+            .filter_map(|name| name.strip_prefix(TOPIC_PREFIX).map(str::to_string))
+            .collect()
+    }
+
+    /// Trees not managed by the broker, e.g. queues written by older tools or
+    /// stores opened through `db`.
+    pub fn raw_trees(&self) -> sled::Result<Vec<(String, usize)>> {
+        let mut trees = Vec::new();
+        for name in self.tree_names() {
+            let managed = [TOPIC_PREFIX, OFFSETS_PREFIX, DEAD_LETTERS_PREFIX]
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+            let internal = [META_TREE, "__sled__default"];
+            if !managed && !internal.contains(&name.as_str()) {
+                trees.push((name.clone(), self.db.open_tree(&name)?.len()));
+            }
+        }
+        if !self.db.is_empty() {
+            trees.push(("__sled__default".to_string(), self.db.len()));
+        }
+        Ok(trees)
+    }
+
+    fn tree_names(&self) -> Vec<String> {
+        self.db
+            .tree_names()
+            .into_iter()
+            .filter_map(|name| String::from_utf8(name.to_vec()).ok())
+            .collect()
+    }
+
+    pub async fn flush(&self) -> sled::Result<usize> {
+        self.db.flush_async().await
+    }
+}
+
+/// An append-only log of messages addressed by offset, encoded with one codec.
+#[derive(Clone)]
+pub struct Topic {
+    name: String,
+    codec: Codec,
+    messages: Tree,
+    offsets: Tree,
+    dead_letters: Tree,
+    meta: Tree,
+    next_offset_key: String,
+    next_offset: Arc<Mutex<u64>>,
+    count: Arc<AtomicUsize>,
+    bytes: Arc<AtomicUsize>,
+    committed: Arc<Notify>,
+    capacity: Capacity,
+}
+
+impl Topic {
+    fn open(db: &Db, name: &str, codec: Codec, capacity: Capacity) -> sled::Result<Self> {
+        let messages = db.open_tree(format!("{}{}", TOPIC_PREFIX, name))?;
+        let offsets = db.open_tree(format!("{}{}", OFFSETS_PREFIX, name))?;
+        let dead_letters = db.open_tree(format!("{}{}", DEAD_LETTERS_PREFIX, name))?;
+        let meta = db.open_tree(META_TREE)?;
+        let next_offset_key = format!("{}{}", NEXT_OFFSET_PREFIX, name);
+
+        // Offsets must keep increasing even once every message was removed,
+        // or groups positioned past them would skip new messages. Databases
+        // written before the next offset was persisted only have the last
+        // message and the committed groups to go by.
+        let mut next_offset = meta
+            .get(&next_offset_key)?
+            .map(|v| decode_offset(&v))
+            .unwrap_or(0);
+        if let Some((key, _)) = messages.last()? {
+            next_offset = next_offset.max(decode_offset(&key) + 1);
+        }
+        for offset in offsets.iter().values() {
+            next_offset = next_offset.max(decode_offset(&offset?));
+        }
+        let mut count = 0;
+        let mut bytes = 0;
+        for item in messages.iter() {
+            let (_, value) = item?;
+            count += 1;
+            bytes += value.len();
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            codec,
+            messages,
+            offsets,
+            dead_letters,
+            meta,
+            next_offset_key,
+            next_offset: Arc::new(Mutex::new(next_offset)),
+            count: Arc::new(AtomicUsize::new(count)),
+            bytes: Arc::new(AtomicUsize::new(bytes)),
+            committed: Arc::new(Notify::new()),
+            capacity,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Appends `value`, returning its offset, or `None` if the
+    /// `DropNewest` policy discarded it because the topic is full.
+    pub async fn publish<T: Serialize>(&self, value: &T) -> sled::Result<Option<u64>> {
+        let payload = self.codec.encode(value).map_err(to_sled_error)?;
+        self.append(&payload).await
+    }
+
+    /// Appends a whole record batch to an Arrow IPC topic.
+    pub async fn publish_batch(&self, batch: &RecordBatch) -> sled::Result<Option<u64>> {
+        let payload = self.codec.encode_batch(batch).map_err(to_sled_error)?;
+        self.append(&payload).await
+    }
+
+    async fn append(&self, payload: &[u8]) -> sled::Result<Option<u64>> {
+        let mut record = Vec::with_capacity(TIMESTAMP_LEN + payload.len());
+        record.extend_from_slice(&now_millis().to_be_bytes());
+        record.extend_from_slice(payload);
+
+        if !self.capacity.allows(1, record.len()) {
+            return Err(sled::Error::Unsupported(format!(
+                "message of {} bytes exceeds the capacity of topic {}",
+                record.len(),
+                self.name
+            )));
+        }
+
+        let mut next_offset = self.next_offset.lock().await;
+        if !self.make_room(record.len()).await? {
+            return Ok(None);
+        }
+        let offset = *next_offset;
+        // Counted before the insert so that a concurrent removal of the new
+        // message never takes the counters below zero.
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.bytes.fetch_add(record.len(), Ordering::SeqCst);
+        // The message and the next offset are written together, so a restart
+        // never hands out an offset twice.
+        let written = (&self.messages, &self.meta).transaction(|(messages, meta)| {
+            messages.insert(&offset.to_be_bytes()[..], record.as_slice())?;
+            meta.insert(
+                self.next_offset_key.as_bytes(),
+                &(offset + 1).to_be_bytes()[..],
+            )?;
+            Ok(())
+        });
+        if let Err(e) = written {
+            self.count.fetch_sub(1, Ordering::SeqCst);
+            self.bytes.fetch_sub(record.len(), Ordering::SeqCst);
+            return Err(match e {
+                TransactionError::Abort(e) | TransactionError::Storage(e) => e,
+            });
+        }
+        *next_offset += 1;
+
+        Ok(Some(offset))
+    }
+
+    fn fits(&self, record_len: usize) -> bool {
+        self.capacity
+            .allows(self.len() + 1, self.size_in_bytes() + record_len)
+    }
+
+    async fn make_room(&self, record_len: usize) -> sled::Result<bool> {
+        while !self.fits(record_len) {
+            match self.capacity.policy {
+                OverflowPolicy::DropNewest => return Ok(false),
+                OverflowPolicy::DropOldest => {
+                    if !self.remove_first()? {
+                        break;
+                    }
+                }
+                OverflowPolicy::Block => {
+                    if self.release_consumed()? == 0 {
+                        // Retention can also free space, so do not rely on commits alone.
+                        let _ = tokio::time::timeout(
+                            BLOCK_RECHECK_INTERVAL,
+                            self.committed.notified(),
+                        )
+                        .await;
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn remove_first(&self) -> sled::Result<bool> {
+        match self.messages.pop_min()? {
+            Some((_, value)) => {
+                self.count.fetch_sub(1, Ordering::SeqCst);
+                self.bytes.fetch_sub(value.len(), Ordering::SeqCst);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Removes messages every consumer group has committed, returning how many were removed.
+    pub fn release_consumed(&self) -> sled::Result<usize> {
+        let low_watermark = match self.groups()?.into_iter().map(|(_, offset)| offset).min() {
+            Some(offset) => offset,
+            None => return Ok(0),
+        };
+        let mut removed = 0;
+        while let Some((key, _)) = self.messages.first()? {
+            if decode_offset(&key) >= low_watermark || !self.remove_first()? {
+                break;
+            }
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    pub fn consumer(&self, group: &str) -> Consumer {
+        Consumer {
+            topic: self.clone(),
+            group: group.to_string(),
+        }
+    }
+
+    /// Offset of the oldest message still retained.
+    pub fn first_offset(&self) -> sled::Result<Option<u64>> {
+        Ok(self.messages.first()?.map(|(k, _)| decode_offset(&k)))
+    }
+
+    pub async fn end_offset(&self) -> u64 {
+        *self.next_offset.lock().await
+    }
+
+    /// Number of messages currently stored.
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes currently stored, timestamps included.
+    pub fn size_in_bytes(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn capacity(&self) -> &Capacity {
+        &self.capacity
+    }
+
+    /// Up to `count` messages starting at `offset`, without consuming them.
+    pub fn peek<T: DeserializeOwned>(
+        &self,
+        offset: u64,
+        count: usize,
+    ) -> sled::Result<Vec<Message<T>>> {
+        self.peek_with(offset, count, |payload| self.decode(payload))
+    }
+
+    /// Like `peek`, rendering payloads of any self-describing codec as JSON.
+    pub fn peek_json(&self, offset: u64, count: usize) -> sled::Result<Vec<Message>> {
+        self.peek_with(offset, count, |payload| {
+            self.codec.to_json(payload).map_err(to_sled_error)
+        })
+    }
+
+    fn peek_with<T>(
+        &self,
+        offset: u64,
+        count: usize,
+        decode: impl Fn(&[u8]) -> sled::Result<T>,
+    ) -> sled::Result<Vec<Message<T>>> {
+        self.messages
+            .range(offset.to_be_bytes()..)
+            .take(count)
+            .map(|item| {
+                let (key, record) = item?;
+                Message::decode(&key, &record, &decode)
+            })
+            .collect()
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> sled::Result<T> {
+        self.codec.decode(payload).map_err(to_sled_error)
+    }
+
+    pub fn dead_letter_count(&self) -> usize {
+        self.dead_letters.len()
+    }
+
+    pub fn dead_letters(&self, count: usize) -> sled::Result<Vec<DeadLetter>> {
+        self.dead_letters
+            .iter()
+            .values()
+            .take(count)
+            .map(|value| bincode::deserialize(&value?).map_err(to_sled_error))
+            .collect()
+    }
+
+    /// Publishes every dead letter again at the end of the topic, returning how many were moved.
+    pub async fn requeue_dead_letters(&self) -> sled::Result<usize> {
+        let mut requeued = 0;
+        while let Some((key, value)) = self.dead_letters.first()? {
+            let dead_letter: DeadLetter = bincode::deserialize(&value).map_err(to_sled_error)?;
+            if self.append(&dead_letter.payload).await?.is_none() {
+                break;
+            }
+            self.dead_letters.remove(key)?;
+            requeued += 1;
+        }
+        Ok(requeued)
+    }
+
+    /// Removes all messages and dead letters. Offsets keep increasing so groups never re-read.
+    pub async fn purge(&self) -> sled::Result<usize> {
+        let _next_offset = self.next_offset.lock().await;
+        let removed = self.len() + self.dead_letters.len();
+        self.messages.clear()?;
+        self.dead_letters.clear()?;
+        self.count.store(0, Ordering::SeqCst);
+        self.bytes.store(0, Ordering::SeqCst);
+        self.committed.notify_waiters();
+        Ok(removed)
+    }
+
+    pub fn groups(&self) -> sled::Result<Vec<(String, u64)>> {
+        let mut groups = Vec::new();
+        for item in self.offsets.iter() {
+            let (group, offset) = item?;
+            groups.push((
+                String::from_utf8_lossy(&group).to_string(),
+                decode_offset(&offset),
+            ));
+        }
+        Ok(groups)
+    }
+
+    /// Drops the oldest messages exceeding `retention`, returning how many were removed.
+    pub fn apply_retention(&self, retention: &Retention) -> sled::Result<usize> {
+        let mut removed = 0;
+
+        if let Some(max_age) = retention.max_age {
+            let cutoff = now_millis().saturating_sub(max_age.as_millis() as u64);
+            while let Some((_, value)) = self.messages.first()? {
+                if decode_timestamp(&value) >= cutoff || !self.remove_first()? {
+                    break;
+                }
+                removed += 1;
+            }
+        }
+
+        if let Some(max_bytes) = retention.max_bytes {
+            while self.size_in_bytes() > max_bytes && self.remove_first()? {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Reads a topic on behalf of a consumer group whose offset is persisted.
+///
+/// Every group sees every message: two groups on the same topic progress
+/// independently.
+pub struct Consumer {
+    topic: Topic,
+    group: String,
+}
+
+impl Consumer {
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    /// Next offset this group will read.
+    pub fn position(&self) -> sled::Result<u64> {
+        Ok(self
+            .topic
+            .offsets
+            .get(&self.group)?
+            .map(|v| decode_offset(&v))
+            .unwrap_or(0))
+    }
+
+    /// Returns the next message without committing it.
+    pub fn poll<T: DeserializeOwned>(&self) -> sled::Result<Option<Message<T>>> {
+        self.poll_with(|payload| self.topic.decode(payload))
+    }
+
+    /// Returns the next record batch of an Arrow IPC topic without committing it.
+    pub fn poll_batch(&self) -> sled::Result<Option<Message<RecordBatch>>> {
+        self.poll_with(|payload| {
+            self.topic
+                .codec
+                .decode_batch(payload)
+                .map_err(to_sled_error)
+        })
+    }
+
+    fn poll_with<T>(
+        &self,
+        decode: impl FnOnce(&[u8]) -> sled::Result<T>,
+    ) -> sled::Result<Option<Message<T>>> {
+        let position = self.position()?;
+        match self.topic.messages.range(position.to_be_bytes()..).next() {
+            Some(item) => {
+                let (key, record) = item?;
+                Ok(Some(Message::decode(&key, &record, decode)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Sets `message` aside in the topic's dead letters and moves past it.
+    pub fn dead_letter<T>(&self, message: &Message<T>, reason: &str) -> sled::Result<()> {
+        let record = self
+            .topic
+            .messages
+            .get(message.offset.to_be_bytes())?
+            .ok_or_else(|| {
+                sled::Error::Unsupported(format!(
+                    "offset {} of topic {} is no longer retained",
+                    message.offset, self.topic.name
+                ))
+            })?;
+        let dead_letter = DeadLetter {
+            group: self.group.clone(),
+            reason: reason.to_string(),
+            offset: message.offset,
+            timestamp: message.timestamp,
+            payload: record[TIMESTAMP_LEN..].to_vec(),
+        };
+        let value = bincode::serialize(&dead_letter).map_err(to_sled_error)?;
+        let mut key = message.offset.to_be_bytes().to_vec();
+        key.extend_from_slice(self.group.as_bytes());
+        self.topic.dead_letters.insert(key, value)?;
+        self.commit(message)
+    }
+
+    /// Marks `message` and everything before it as processed by this group.
+    pub fn commit<T>(&self, message: &Message<T>) -> sled::Result<()> {
+        self.seek(message.offset + 1)
+    }
+
+    pub fn seek(&self, offset: u64) -> sled::Result<()> {
+        self.topic
+            .offsets
+            .insert(&self.group, &offset.to_be_bytes())?;
+        self.topic.committed.notify_waiters();
+        Ok(())
+    }
+
+    pub async fn lag(&self) -> sled::Result<u64> {
+        let position = self.position()?;
+        let first = self.topic.first_offset()?.unwrap_or(position);
+        Ok(self
+            .topic
+            .end_offset()
+            .await
+            .saturating_sub(position.max(first)))
+    }
+}
+
+fn decode_offset(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes.try_into().unwrap())
+}
+
+fn decode_timestamp(record: &[u8]) -> u64 {
+    u64::from_be_bytes(record[..TIMESTAMP_LEN].try_into().unwrap())
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn to_sled_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> sled::Error {
+    sled::Error::Io(std::io::Error::other(e))
+}
@@ -0,0 +1,174 @@
+// src/lib.rs
+use arrow::error::ArrowError;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::json::ArrayWriter;
+use arrow::record_batch::RecordBatch;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Encoding of the payloads kept in a sled tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    Bincode,
+    MessagePack,
+    /// One Arrow IPC stream per payload, holding a whole record batch.
+    ArrowIpc,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    MessagePackEncode(rmp_serde::encode::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+    Arrow(ArrowError),
+    Unsupported(Codec, &'static str),
+}
+
+impl std::error::Error for CodecError {}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Json(e) => write!(f, "JSON codec error: {}", e),
+            CodecError::Bincode(e) => write!(f, "Bincode codec error: {}", e),
+            CodecError::MessagePackEncode(e) => write!(f, "MessagePack encode error: {}", e),
+            CodecError::MessagePackDecode(e) => write!(f, "MessagePack decode error: {}", e),
+            CodecError::Arrow(e) => write!(f, "Arrow IPC codec error: {}", e),
+            CodecError::Unsupported(codec, operation) => {
+                write!(f, "The {} codec cannot {}", codec, operation)
+            }
+        }
+    }
+}
+
+impl From<serde_json::Error> for CodecError {
+    fn from(e: serde_json::Error) -> Self {
+        CodecError::Json(e)
+    }
+}
+
+impl From<bincode::Error> for CodecError {
+    fn from(e: bincode::Error) -> Self {
+        CodecError::Bincode(e)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for CodecError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        CodecError::MessagePackEncode(e)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for CodecError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        CodecError::MessagePackDecode(e)
+    }
+}
+
+impl From<ArrowError> for CodecError {
+    fn from(e: ArrowError) -> Self {
+        CodecError::Arrow(e)
+    }
+}
+
+impl Codec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Bincode => "bincode",
+            Codec::MessagePack => "msgpack",
+            Codec::ArrowIpc => "arrow_ipc",
+        }
+    }
+
+    /// Whether payloads can be read back without knowing the Rust type that wrote them.
+    pub fn is_self_describing(self) -> bool {
+        !matches!(self, Codec::Bincode)
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(value)?),
+            Codec::Bincode => Ok(bincode::serialize(value)?),
+            Codec::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+            Codec::ArrowIpc => Err(CodecError::Unsupported(self, "encode serde values")),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Json => Ok(serde_json::from_slice(bytes)?),
+            Codec::Bincode => Ok(bincode::deserialize(bytes)?),
+            Codec::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+            Codec::ArrowIpc => Err(CodecError::Unsupported(self, "decode serde values")),
+        }
+    }
+
+    pub fn encode_batch(self, batch: &RecordBatch) -> Result<Vec<u8>, CodecError> {
+        if self != Codec::ArrowIpc {
+            return Err(CodecError::Unsupported(self, "encode record batches"));
+        }
+        let mut buffer = Vec::new();
+        let mut writer = StreamWriter::try_new(&mut buffer, &batch.schema())?;
+        writer.write(batch)?;
+        writer.finish()?;
+        drop(writer);
+        Ok(buffer)
+    }
+
+    pub fn decode_batch(self, bytes: &[u8]) -> Result<RecordBatch, CodecError> {
+        if self != Codec::ArrowIpc {
+            return Err(CodecError::Unsupported(self, "decode record batches"));
+        }
+        let mut reader = StreamReader::try_new(Cursor::new(bytes), None)?;
+        match reader.next() {
+            Some(batch) => Ok(batch?),
+            None => Err(CodecError::Arrow(ArrowError::IpcError(
+                "stream holds no record batch".to_string(),
+            ))),
+        }
+    }
+
+    /// Renders a payload as JSON for inspection; record batches become an array of rows.
+    pub fn to_json(self, bytes: &[u8]) -> Result<Value, CodecError> {
+        match self {
+            Codec::ArrowIpc => {
+                let batch = self.decode_batch(bytes)?;
+                let mut writer = ArrayWriter::new(Vec::new());
+                writer.write(&batch)?;
+                writer.finish()?;
+                Ok(serde_json::from_slice(&writer.into_inner())?)
+            }
+            Codec::Bincode => Err(CodecError::Unsupported(self, "render payloads as JSON")),
+            _ => self.decode(bytes),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "json" => Ok(Codec::Json),
+            "bincode" => Ok(Codec::Bincode),
+            "msgpack" => Ok(Codec::MessagePack),
+            "arrow_ipc" => Ok(Codec::ArrowIpc),
+            _ => Err(format!("unknown codec: {}", name)),
+        }
+    }
+}
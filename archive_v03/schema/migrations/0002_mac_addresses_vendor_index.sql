@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_mac_addresses_vendor ON mac_addresses (mac_vendor_id);
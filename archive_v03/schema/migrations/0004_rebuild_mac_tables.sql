@@ -0,0 +1,27 @@
+-- 0001 only creates missing tables, so databases created from the API
+-- server's old database.sql kept AUTOINCREMENT ids and `org_name NOT NULL`,
+-- which rejects the placeholder vendors the processor inserts. Rebuild both
+-- tables with the definitions from 0001. Foreign keys are not enforced on
+-- these connections, so the old tables can be dropped in place.
+CREATE TABLE mac_vendors_new (
+    id INTEGER PRIMARY KEY,
+    designation TEXT NOT NULL,
+    org_name TEXT,
+    prefix_bits INTEGER NOT NULL DEFAULT 24
+);
+INSERT INTO mac_vendors_new (id, designation, org_name, prefix_bits)
+    SELECT id, designation, org_name, prefix_bits FROM mac_vendors;
+DROP TABLE mac_vendors;
+ALTER TABLE mac_vendors_new RENAME TO mac_vendors;
+
+CREATE TABLE mac_addresses_new (
+    id INTEGER PRIMARY KEY,
+    mac_address TEXT UNIQUE NOT NULL,
+    mac_vendor_id INTEGER,
+    FOREIGN KEY(mac_vendor_id) REFERENCES mac_vendors(id)
+);
+INSERT INTO mac_addresses_new (id, mac_address, mac_vendor_id)
+    SELECT id, mac_address, mac_vendor_id FROM mac_addresses;
+DROP TABLE mac_addresses;
+ALTER TABLE mac_addresses_new RENAME TO mac_addresses;
+CREATE INDEX idx_mac_addresses_vendor ON mac_addresses (mac_vendor_id);
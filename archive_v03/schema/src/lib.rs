@@ -0,0 +1,206 @@
+// src/lib.rs
+//
+// Shared by the API server (test_v03) and the MAC processor (test_v05), so
+// both programs create and upgrade the same tables.
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::fmt;
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub step: Step,
+}
+
+pub enum Step {
+    Sql(&'static str),
+    /// For changes that depend on what the database already contains, which
+    /// plain SQL cannot check.
+    Code(fn(&Connection) -> rusqlite::Result<()>),
+}
+
+/// Every schema change, in order. Never edit a released migration: databases
+/// that already applied it will not run it again. Add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        step: Step::Sql(include_str!("../migrations/0001_initial.sql")),
+    },
+    Migration {
+        version: 2,
+        name: "mac_addresses_vendor_index",
+        step: Step::Sql(include_str!("../migrations/0002_mac_addresses_vendor_index.sql")),
+    },
+    Migration {
+        version: 3,
+        name: "mac_vendors_prefix_bits",
+        step: Step::Code(add_vendor_prefix_bits),
+    },
+    Migration {
+        version: 4,
+        name: "rebuild_mac_tables",
+        step: Step::Sql(include_str!("../migrations/0004_rebuild_mac_tables.sql")),
+    },
+];
+
+/// `0001_initial` only creates missing tables, so a database created before
+/// vendors were matched by prefix length reached version 1 without the column.
+fn add_vendor_prefix_bits(conn: &Connection) -> rusqlite::Result<()> {
+    if !has_column(conn, "mac_vendors", "prefix_bits")? {
+        conn.execute_batch(
+            "ALTER TABLE mac_vendors ADD COLUMN prefix_bits INTEGER NOT NULL DEFAULT 24",
+        )?;
+    }
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )
+}
+
+/// Database used when `MACS_DB` is not set.
+pub const DEFAULT_DB_PATH: &str = "macs.db";
+
+/// Path of the SQLite database, from `MACS_DB` or `DEFAULT_DB_PATH`. Both
+/// programs resolve it the same way so they open the same file.
+pub fn database_path() -> String {
+    std::env::var("MACS_DB").unwrap_or_else(|_| DEFAULT_DB_PATH.to_string())
+}
+
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+)";
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    /// The database was migrated by a newer build than this one.
+    UnknownVersion(u32),
+}
+
+impl std::error::Error for MigrationError {}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "Migration failed: {}", e),
+            MigrationError::UnknownVersion(version) => write!(
+                f,
+                "Database schema version {} is newer than the latest known version {}",
+                version,
+                latest_version()
+            ),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+/// One row of `schema status`: `applied_at` is `None` for pending migrations.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    pub applied_at: Option<String>,
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Highest applied version, 0 for a database that was never migrated.
+pub fn current_version(conn: &Connection) -> Result<u32, MigrationError> {
+    conn.execute(CREATE_SCHEMA_VERSION, [])?;
+    let version: Option<u32> =
+        conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+            row.get(0)
+        })?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Applies all pending migrations in a single transaction and returns the
+/// versions applied. Either the database ends up at the latest version or it
+/// is left untouched.
+///
+/// The transaction takes the write lock before reading the version, so when
+/// the API server and the processor start together one migrates and the other
+/// waits, then finds nothing left to apply.
+pub fn migrate(conn: &mut Connection) -> Result<Vec<u32>, MigrationError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let current = current_version(&tx)?;
+    if current > latest_version() {
+        return Err(MigrationError::UnknownVersion(current));
+    }
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        match migration.step {
+            Step::Sql(sql) => tx.execute_batch(sql)?,
+            Step::Code(run) => run(&tx)?,
+        }
+        tx.execute(
+            "INSERT INTO schema_version (version, name) VALUES (?, ?)",
+            params![migration.version, migration.name],
+        )?;
+        applied.push(migration.version);
+    }
+    tx.commit()?;
+    Ok(applied)
+}
+
+/// Known migrations with the time each was applied, followed by any versions
+/// recorded in the database that this build does not know about.
+pub fn status(conn: &Connection) -> Result<Vec<MigrationStatus>, MigrationError> {
+    conn.execute(CREATE_SCHEMA_VERSION, [])?;
+    let mut applied_at = conn.prepare("SELECT applied_at FROM schema_version WHERE version = ?")?;
+    let mut statuses = Vec::with_capacity(MIGRATIONS.len());
+    for migration in MIGRATIONS {
+        statuses.push(MigrationStatus {
+            version: migration.version,
+            name: migration.name.to_string(),
+            applied_at: applied_at
+                .query_row([migration.version], |row| row.get(0))
+                .optional()?,
+        });
+    }
+
+    let mut unknown = conn.prepare(
+        "SELECT version, name, applied_at FROM schema_version WHERE version > ? ORDER BY version",
+    )?;
+    let rows = unknown.query_map([latest_version()], |row| {
+        Ok(MigrationStatus {
+            version: row.get(0)?,
+            name: row.get(1)?,
+            applied_at: row.get(2)?,
+        })
+    })?;
+    for row in rows {
+        statuses.push(row?);
+    }
+    Ok(statuses)
+}
+
+/// Prints `status` as a table, one migration per line.
+pub fn print_status(conn: &Connection) -> Result<(), MigrationError> {
+    let latest = latest_version();
+    for migration in status(conn)? {
+        let state = match (&migration.applied_at, migration.version > latest) {
+            (Some(at), false) => format!("applied {}", at),
+            (Some(at), true) => format!("applied {} (unknown to this build)", at),
+            (None, _) => "pending".to_string(),
+        };
+        println!("{:>4}  {:<32} {}", migration.version, migration.name, state);
+    }
+    println!("Schema version {} of {}", current_version(conn)?, latest);
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use datafusion::arrow::{
-    array::{Float64Array, StringArray},
+    array::{Float64Array, Int64Array, StringArray},
     datatypes::{DataType, Field, Schema},
     record_batch::RecordBatch,
 };
@@ -8,15 +8,28 @@ use datafusion::datasource::memory::MemTable;
 use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::SessionContext;
 use datafusion::prelude::*;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+// Keeps first_seen, only moves last_seen forward and adds up sightings,
+// whatever order the batches arrive in.
+const UPSERT_MAC: &str = "INSERT INTO macs (mac_address_id, mac_address, first_seen, last_seen, mac_vendor_id, sightings)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ON CONFLICT(mac_address_id) DO UPDATE SET
+        first_seen = CASE WHEN macs.first_seen IS NULL OR excluded.first_seen < macs.first_seen
+                          THEN excluded.first_seen ELSE macs.first_seen END,
+        last_seen = CASE WHEN macs.last_seen IS NULL OR excluded.last_seen > macs.last_seen
+                         THEN excluded.last_seen ELSE macs.last_seen END,
+        mac_vendor_id = excluded.mac_vendor_id,
+        sightings = macs.sightings + excluded.sightings";
+
 // TO DO : add contexte to event processor
 pub struct EventProcessor {
     fields: Vec<String>,
     schema: Arc<Schema>,
+    keep_history: bool,
 }
 
 impl EventProcessor {
@@ -27,7 +40,17 @@ impl EventProcessor {
                 .map(|field| Field::new(field.as_str(), DataType::Utf8, false))
                 .collect::<Vec<_>>(),
         ));
-        EventProcessor { fields, schema }
+        EventProcessor {
+            fields,
+            schema,
+            keep_history: false,
+        }
+    }
+
+    /// Also record which source IP each MAC used over time in `mac_ip_history`.
+    pub fn with_history(mut self, keep_history: bool) -> Self {
+        self.keep_history = keep_history;
+        self
     }
 
     pub async fn process(&mut self, events: Vec<Value>) -> Result<()> {
@@ -45,69 +68,88 @@ impl EventProcessor {
         let ctx = SessionContext::new();
         ctx.register_table("mac_table", Arc::new(table))?;
 
-        let df = ctx.sql("SELECT * FROM mac_table").await?;
-        //df.clone().show().await?;
-
         let df = ctx
-                    .sql("SELECT mac_address,max(event_time) as event_time FROM mac_table GROUP BY mac_address")
-                    .await?;
-        df.clone().show().await?;
-
-    let batches = df.collect().await?;
-
-    let mut conn = Connection::open("macs.db").map_err(|e| {
-        DataFusionError::Internal(format!("Error opening database connection: {}", e))
-    })?;
-    let tx = conn.transaction().map_err(|e| {
-        DataFusionError::Internal(format!("Error starting transaction: {}", e))
-    })?;
-
-
-    for batch in batches {
-        let mac_address_col = batch
-            .column(0)
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .ok_or_else(|| DataFusionError::Internal("Failed to cast mac_address column".to_string()))?;
-        let event_time_col = batch
-            .column(1)
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .ok_or_else(|| DataFusionError::Internal("Failed to cast event_time column".to_string()))?;
-
-        for i in 0..batch.num_rows() {
-            let mac_address = mac_address_col.value(i);
-            let event_time = event_time_col.value(i);
-
-            let mac_address_id = get_mac_id(mac_address);
-            let vendor_id = get_vendor_id(mac_address);
-
-            match (mac_address_id, vendor_id) {
-                (Some(mac_id), Some(vendor)) => {
-                    tx.execute(
-                        "INSERT OR REPLACE INTO macs (mac_address_id, mac_address, last_seen, mac_vendor_id) VALUES (?1, ?2, ?3, ?4)",
-                        params![mac_id, mac_address, event_time, vendor],
-                    )
-                    .map_err(|e| {
-                        DataFusionError::Internal(format!("Error executing SQL query: {}", e))
-                    })?;
-                }
-                (Some(mac_id), None) => {
-                    println!(
-                        "Row {}: mac_address={}, event_time={}, mac_address_id={}, Vendor extraction failed",
-                        i, mac_address, event_time, mac_id
-                    );
-                }
-                (None, _) => {
-                    println!("Row {}: Invalid MAC address: {}", i, mac_address);
+            .sql(
+                "SELECT mac_address, min(event_time) AS first_seen, max(event_time) AS last_seen, \
+                 count(*) AS sightings FROM mac_table GROUP BY mac_address",
+            )
+            .await?;
+        let batches = df.collect().await?;
+
+        let history = if self.keep_history {
+            ctx.sql(
+                "SELECT mac_address, ip_address_src, min(event_time) AS first_seen, \
+                 max(event_time) AS last_seen, count(*) AS sightings FROM mac_table \
+                 GROUP BY mac_address, ip_address_src ORDER BY mac_address, first_seen",
+            )
+            .await?
+            .collect()
+            .await?
+        } else {
+            Vec::new()
+        };
+
+        let mut conn = Connection::open("macs.db").map_err(|e| {
+            DataFusionError::Internal(format!("Error opening database connection: {}", e))
+        })?;
+        let tx = conn.transaction().map_err(|e| {
+            DataFusionError::Internal(format!("Error starting transaction: {}", e))
+        })?;
+
+        for batch in batches {
+            let mac_address_col = string_column(&batch, 0, "mac_address")?;
+            let first_seen_col = string_column(&batch, 1, "first_seen")?;
+            let last_seen_col = string_column(&batch, 2, "last_seen")?;
+            let sightings_col = batch
+                .column(3)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .ok_or_else(|| {
+                    DataFusionError::Internal("Failed to cast sightings column".to_string())
+                })?;
+
+            for i in 0..batch.num_rows() {
+                let mac_address = mac_address_col.value(i);
+                let first_seen = first_seen_col.value(i);
+                let last_seen = last_seen_col.value(i);
+
+                let mac_address_id = get_mac_id(mac_address);
+                let vendor_id = get_vendor_id(mac_address);
+
+                match (mac_address_id, vendor_id) {
+                    (Some(mac_id), Some(vendor)) => {
+                        tx.execute(UPSERT_MAC, params![
+                            mac_id,
+                            mac_address,
+                            first_seen,
+                            last_seen,
+                            vendor,
+                            sightings_col.value(i)
+                        ])
+                        .map_err(|e| {
+                            DataFusionError::Internal(format!("Error executing SQL query: {}", e))
+                        })?;
+                    }
+                    (Some(mac_id), None) => {
+                        println!(
+                            "Row {}: mac_address={}, last_seen={}, mac_address_id={}, Vendor extraction failed",
+                            i, mac_address, last_seen, mac_id
+                        );
+                    }
+                    (None, _) => {
+                        println!("Row {}: Invalid MAC address: {}", i, mac_address);
+                    }
                 }
             }
         }
-    }
 
-    tx.commit().map_err(|e| {
-        DataFusionError::Internal(format!("Error committing transaction: {}", e))
-    })?;
+        for batch in &history {
+            record_history(&tx, batch)?;
+        }
+
+        tx.commit().map_err(|e| {
+            DataFusionError::Internal(format!("Error committing transaction: {}", e))
+        })?;
         Ok(())
     }
 
@@ -163,6 +205,101 @@ impl EventProcessor {
     }
 }
 
+fn string_column<'a>(
+    batch: &'a RecordBatch,
+    index: usize,
+    name: &str,
+) -> Result<&'a StringArray> {
+    batch
+        .column(index)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .ok_or_else(|| DataFusionError::Internal(format!("Failed to cast {} column", name)))
+}
+
+fn sql_error(e: rusqlite::Error) -> DataFusionError {
+    DataFusionError::Internal(format!("Error executing SQL query: {}", e))
+}
+
+/// Extends the MAC's latest window when the IP is unchanged, otherwise opens
+/// a new (IP, first_seen, last_seen) window.
+fn record_history(tx: &Transaction, batch: &RecordBatch) -> Result<()> {
+    let mac_address_col = string_column(batch, 0, "mac_address")?;
+    let ip_address_col = string_column(batch, 1, "ip_address_src")?;
+    let first_seen_col = string_column(batch, 2, "first_seen")?;
+    let last_seen_col = string_column(batch, 3, "last_seen")?;
+    let sightings_col = batch
+        .column(4)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| DataFusionError::Internal("Failed to cast sightings column".to_string()))?;
+
+    let mut latest_stmt = tx
+        .prepare_cached(
+            "SELECT id, ip_address, first_seen FROM mac_ip_history \
+             WHERE mac_address_id = ?1 ORDER BY first_seen DESC LIMIT 1",
+        )
+        .map_err(sql_error)?;
+    let mut extend_stmt = tx
+        .prepare_cached(
+            "UPDATE mac_ip_history SET \
+             last_seen = CASE WHEN ?2 > last_seen THEN ?2 ELSE last_seen END, \
+             sightings = sightings + ?3 WHERE id = ?1",
+        )
+        .map_err(sql_error)?;
+    let mut insert_stmt = tx
+        .prepare_cached(
+            "INSERT INTO mac_ip_history (mac_address_id, ip_address, first_seen, last_seen, sightings) \
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .map_err(sql_error)?;
+
+    for i in 0..batch.num_rows() {
+        let Some(mac_id) = get_mac_id(mac_address_col.value(i)) else {
+            continue;
+        };
+        let ip_address = ip_address_col.value(i);
+        let first_seen = first_seen_col.value(i);
+        let last_seen = last_seen_col.value(i);
+        let sightings = sightings_col.value(i);
+
+        let latest: Option<(i64, String, String)> = latest_stmt
+            .query_row(params![mac_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()
+            .map_err(sql_error)?;
+        match latest {
+            Some((id, ip, window_start)) if ip == ip_address && first_seen >= window_start.as_str() => {
+                extend_stmt
+                    .execute(params![id, last_seen, sightings])
+                    .map_err(sql_error)?;
+            }
+            _ => {
+                insert_stmt
+                    .execute(params![mac_id, ip_address, first_seen, last_seen, sightings])
+                    .map_err(sql_error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The source IP `mac_address` was seen with at `time` (RFC 3339), if any.
+pub fn ip_at(conn: &Connection, mac_address: &str, time: &str) -> rusqlite::Result<Option<String>> {
+    let Some(mac_id) = get_mac_id(mac_address) else {
+        return Ok(None);
+    };
+    conn.query_row(
+        "SELECT ip_address FROM mac_ip_history \
+         WHERE mac_address_id = ?1 AND first_seen <= ?2 AND last_seen >= ?2 \
+         ORDER BY first_seen DESC LIMIT 1",
+        params![mac_id, time],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
 fn get_mac_id(mac: &str) -> Option<u64> {
     let mac_clean = mac.replace(":", ""); // Remove colons
     u64::from_str_radix(&mac_clean, 16).ok() // Convert hex string to integer
@@ -2,7 +2,7 @@ mod event_generator;
 mod event_processor;
 
 use event_generator::EventGenerator;
-use event_processor::EventProcessor;
+use event_processor::{ip_at, EventProcessor};
 use rusqlite::Connection;
 use std::result::Result;
 use tokio::sync::mpsc;
@@ -22,10 +22,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             mac_address TEXT NOT NULL,
             first_seen TEXT,
             last_seen TEXT,
-            mac_vendor_id INTEGER
+            mac_vendor_id INTEGER,
+            sightings INTEGER NOT NULL DEFAULT 0
         )",
         [],
     )?;
+    // Databases created before sightings were counted
+    if !has_column(&conn, "macs", "sightings")? {
+        conn.execute(
+            "ALTER TABLE macs ADD COLUMN sightings INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS mac_ip_history (
+            id INTEGER PRIMARY KEY,
+            mac_address_id INTEGER NOT NULL,
+            ip_address TEXT NOT NULL,
+            first_seen TEXT NOT NULL,
+            last_seen TEXT NOT NULL,
+            sightings INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS mac_ip_history_mac_time
+            ON mac_ip_history (mac_address_id, first_seen);",
+    )?;
+
+    // test_v07 ip-at <mac> <rfc3339 time>
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("ip-at") {
+        let (Some(mac_address), Some(time)) = (args.get(2), args.get(3)) else {
+            eprintln!("Usage: test_v07 ip-at <mac> <rfc3339 time>");
+            std::process::exit(1);
+        };
+        match ip_at(&conn, mac_address, time)? {
+            Some(ip_address) => println!("{}", ip_address),
+            None => println!("No IP recorded for {} at {}", mac_address, time),
+        }
+        return Ok(());
+    }
 
     let (tx, mut rx) = mpsc::channel(CHANNEL_SIZE);
 
@@ -38,7 +72,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "port_dst".to_string(),
         "event_type".to_string(),
     ];
-    let mut event_processor = EventProcessor::new(fields);
+    let mut event_processor = EventProcessor::new(fields).with_history(true);
 
     let event_generator = EventGenerator::new(MAC_NUMBER).await;
 
@@ -81,6 +115,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 fn get_macs_count(conn: &Connection) -> Result<i64, rusqlite::Error> {
     let mut stmt = conn.prepare("SELECT COUNT(*) FROM macs")?;
     let mut rows = stmt.query([])?;
@@ -3,24 +3,32 @@ use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Error as RusqliteError;
 use rusqlite::Result;
-use std::fs;
+use schema::{database_path, MigrationError};
 use std::io;
+use std::time::Duration;
 type DbPool = Pool<SqliteConnectionManager>;
 
-pub fn initialize_database(pool: &DbPool) -> Result<()> {
-    let conn = pool.get().expect("Failed to get DB connection");
+/// Brings the schema up to date with the embedded migrations shared with the
+/// MAC processor.
+pub fn initialize_database(pool: &DbPool) -> std::result::Result<(), MigrationError> {
+    let mut conn = pool.get().expect("Failed to get DB connection");
 
-    let sql_script = fs::read_to_string("database.sql").expect("Failed to read database.sql");
-
-    conn.execute_batch(&sql_script)?;
+    let applied = schema::migrate(&mut conn)?;
+    if !applied.is_empty() {
+        println!("Applied schema migrations {:?}", applied);
+    }
 
     println!("Database initialized successfully.");
     Ok(())
 }
 
 pub fn get_pool() -> Pool<SqliteConnectionManager> {
-    // Create a connection pool
-    let manager = SqliteConnectionManager::file("my_database.db");
+    // Create a connection pool on the database the MAC processor writes to
+    // (MACS_DB); wait for its locks instead of failing reads with SQLITE_BUSY.
+    let manager = SqliteConnectionManager::file(database_path()).with_init(|conn| {
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.busy_timeout(Duration::from_secs(5))
+    });
     Pool::new(manager).expect("Failed to create database pool")
 }
 
@@ -48,7 +56,7 @@ pub fn get_mac_vendors(
 
 pub fn get_mac_vendor(
     conn: &PooledConnection<SqliteConnectionManager>,
-    id: i32,
+    id: i64,
 ) -> std::result::Result<Vec<MacVendor>, io::Error> {
     let mut stmt = conn
         .prepare("SELECT id, designation, org_name FROM mac_vendors WHERE id = ?1")
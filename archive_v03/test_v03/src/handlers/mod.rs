@@ -3,10 +3,11 @@ use crate::models::Status;
 use actix_web::{web, HttpResponse, Responder};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
+use schema::database_path;
 
 // Create a database connection pool
 pub fn create_pool() -> Pool<SqliteConnectionManager> {
-    let manager = SqliteConnectionManager::file("my_database.db");
+    let manager = SqliteConnectionManager::file(database_path());
     Pool::new(manager).expect("Failed to create database pool")
 }
 
@@ -42,7 +43,7 @@ pub async fn fetch_mac_vendors(pool: web::Data<Pool<SqliteConnectionManager>>) -
 
 pub async fn fetch_mac_vendor(
     pool: web::Data<Pool<SqliteConnectionManager>>,
-    path: web::Path<i32>,
+    path: web::Path<i64>,
 ) -> impl Responder {
     let id = path.into_inner();
     match pool.get() {
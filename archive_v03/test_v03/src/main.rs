@@ -18,6 +18,26 @@ async fn main() -> std::io::Result<()> {
 
     let pool = get_pool();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("schema") {
+        let result = match args.get(2).map(String::as_str) {
+            Some("status") => {
+                let conn = pool.get().expect("Failed to get DB connection");
+                schema::print_status(&conn)
+            }
+            Some("migrate") => initialize_database(&pool),
+            _ => {
+                eprintln!("Usage: test_v03 schema <status|migrate>");
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     if let Err(e) = initialize_database(&pool) {
         eprintln!("Database initialization failed: {}", e);
         std::process::exit(1);
@@ -9,16 +9,16 @@ pub struct Status {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MacVendor {
-    pub id: i32,
+    pub id: i64,
     pub designation: String,
-    pub org_name: String,
+    pub org_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MacAddress {
     pub id: i32,
     pub mac_address: String,
-    pub mac_vendor_id: i32,
+    pub mac_vendor_id: i64,
 }
 
 pub trait FromRow {
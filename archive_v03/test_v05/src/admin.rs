@@ -0,0 +1,220 @@
+// src/admin.rs
+use crate::error::AppError;
+use crate::quarantine::QUARANTINE_TREE;
+
+use broker::{Broker, Codec, Message, Topic};
+use serde_json::{json, Value};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+const DEFAULT_QUEUE_PATH: &str = "queue_db";
+const DEFAULT_PEEK_COUNT: usize = 10;
+
+const USAGE: &str = "Usage: test_v05 queue [--db <path>] <command>
+
+Commands:
+  list                        topics with depth, in-flight and dead-letter counts
+  peek <topic> [n] [--dead]   print the first n messages (or dead letters) as JSON
+  requeue <topic>             publish dead letters again
+  purge <topic>               remove all messages and dead letters
+  export <topic> <file>       write messages to a JSON Lines file
+  import <topic> <file>       publish every line of a JSON Lines file";
+
+/// Entry point for `test_v05 queue ...`.
+pub async fn run(args: &[String]) -> Result<(), AppError> {
+    let mut path = DEFAULT_QUEUE_PATH.to_string();
+    let mut args = args.to_vec();
+    if let Some(pos) = args.iter().position(|arg| arg == "--db") {
+        args.remove(pos);
+        if pos >= args.len() {
+            return Err(usage_error("--db needs a path"));
+        }
+        path = args.remove(pos);
+    }
+
+    let broker = Broker::open(&path)?;
+    let command = args.first().map(String::as_str).unwrap_or("list");
+
+    match command {
+        "list" => list(&broker).await,
+        "peek" => {
+            let topic = topic_arg(&broker, &args)?;
+            let dead = args.iter().any(|arg| arg == "--dead");
+            let count = match args.get(2).filter(|arg| *arg != "--dead") {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| usage_error(&format!("invalid count: {}", count)))?,
+                None => DEFAULT_PEEK_COUNT,
+            };
+            peek(&topic, count, dead)
+        }
+        "requeue" => {
+            let topic = topic_arg(&broker, &args)?;
+            let requeued = topic.requeue_dead_letters().await?;
+            println!("Requeued {} dead letters on {}", requeued, topic.name());
+            broker.flush().await?;
+            Ok(())
+        }
+        "purge" => {
+            let topic = topic_arg(&broker, &args)?;
+            let removed = topic.purge().await?;
+            println!("Purged {} entries from {}", removed, topic.name());
+            broker.flush().await?;
+            Ok(())
+        }
+        "export" => {
+            let topic = topic_arg(&broker, &args)?;
+            let file = file_arg(&args)?;
+            let exported = export(&topic, file)?;
+            println!("Exported {} messages from {} to {}", exported, topic.name(), file);
+            Ok(())
+        }
+        "import" => {
+            let topic = topic_arg(&broker, &args)?;
+            let file = file_arg(&args)?;
+            let imported = import(&topic, file).await?;
+            println!("Imported {} messages into {} from {}", imported, topic.name(), file);
+            broker.flush().await?;
+            Ok(())
+        }
+        _ => Err(usage_error(&format!("unknown command: {}", command))),
+    }
+}
+
+async fn list(broker: &Broker) -> Result<(), AppError> {
+    println!(
+        "{:<24} {:<10} {:>10} {:>12} {:>10}",
+        "TOPIC", "CODEC", "DEPTH", "BYTES", "DEAD"
+    );
+    for name in broker.topic_names() {
+        let Some(topic) = broker.existing_topic(&name)? else {
+            continue;
+        };
+        println!(
+            "{:<24} {:<10} {:>10} {:>12} {:>10}",
+            name,
+            topic.codec(),
+            topic.len(),
+            topic.size_in_bytes(),
+            topic.dead_letter_count()
+        );
+        for (group, position) in topic.groups()? {
+            let in_flight = topic.consumer(&group).lag().await?;
+            println!(
+                "  group {:<16} position {:>10} in-flight {:>10}",
+                group, position, in_flight
+            );
+        }
+    }
+    for (name, len) in broker.raw_trees()? {
+        if name == QUARANTINE_TREE {
+            continue;
+        }
+        println!("{:<24} {:<10} {:>10} (raw tree)", name, "-", len);
+    }
+    Ok(())
+}
+
+fn peek(topic: &Topic, count: usize, dead: bool) -> Result<(), AppError> {
+    if dead {
+        for dead_letter in topic.dead_letters(count)? {
+            let rendered = json!({
+                "group": dead_letter.group,
+                "reason": dead_letter.reason,
+                "offset": dead_letter.offset,
+                "timestamp": dead_letter.timestamp,
+                "value": render_payload(topic.codec(), &dead_letter.payload),
+            });
+            println!("{}", to_pretty_json(&rendered)?);
+        }
+    } else {
+        let first = topic.first_offset()?.unwrap_or(0);
+        for message in topic.peek_json(first, count)? {
+            println!("{}", to_pretty_json(&message)?);
+        }
+    }
+    Ok(())
+}
+
+fn export(topic: &Topic, path: &str) -> Result<usize, AppError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut next = topic.first_offset()?.unwrap_or(0);
+    let mut exported = 0;
+    loop {
+        let messages = topic.peek_json(next, 1000)?;
+        let last = match messages.last() {
+            Some(last) => last.offset,
+            None => break,
+        };
+        for message in &messages {
+            let line = serde_json::to_string(message)
+                .map_err(|e| AppError::QueueError(e.to_string()))?;
+            writeln!(writer, "{}", line)?;
+        }
+        exported += messages.len();
+        next = last + 1;
+    }
+    writer.flush()?;
+    Ok(exported)
+}
+
+/// Accepts lines written by `export` as well as bare JSON values.
+async fn import(topic: &Topic, path: &str) -> Result<usize, AppError> {
+    if !topic.codec().is_self_describing() || topic.codec() == Codec::ArrowIpc {
+        return Err(AppError::QueueError(format!(
+            "cannot import JSON into topic {} encoded with {}",
+            topic.name(),
+            topic.codec()
+        )));
+    }
+    let reader = BufReader::new(File::open(path)?);
+    let mut imported = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = match serde_json::from_str::<Message>(&line) {
+            Ok(message) => message.value,
+            Err(_) => serde_json::from_str(&line).map_err(|e| {
+                AppError::QueueError(format!("{}:{}: {}", path, number + 1, e))
+            })?,
+        };
+        if topic.publish(&value).await?.is_some() {
+            imported += 1;
+        }
+    }
+    Ok(imported)
+}
+
+/// Only `import` creates the topic it names.
+fn topic_arg(broker: &Broker, args: &[String]) -> Result<Topic, AppError> {
+    let name = args.get(1).ok_or_else(|| usage_error("missing topic"))?;
+    if args[0] == "import" {
+        return Ok(broker.topic(name)?);
+    }
+    broker
+        .existing_topic(name)?
+        .ok_or_else(|| AppError::QueueError(format!("no such topic: {}", name)))
+}
+
+fn file_arg(args: &[String]) -> Result<&str, AppError> {
+    args.get(2)
+        .map(String::as_str)
+        .ok_or_else(|| usage_error("missing file"))
+}
+
+fn render_payload(codec: Codec, payload: &[u8]) -> Value {
+    codec
+        .to_json(payload)
+        .unwrap_or_else(|_| json!(format!("<{} bytes of {}>", payload.len(), codec)))
+}
+
+fn to_pretty_json<T: serde::Serialize>(value: &T) -> Result<String, AppError> {
+    serde_json::to_string_pretty(value).map_err(|e| AppError::QueueError(e.to_string()))
+}
+
+fn usage_error(msg: &str) -> AppError {
+    AppError::QueueError(format!("{}\n\n{}", msg, USAGE))
+}
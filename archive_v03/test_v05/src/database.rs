@@ -2,28 +2,75 @@
 use crate::error::AppError;
 use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{Connection, Error as RusqliteError};
-use std::fs;
+use rusqlite::Connection;
+pub use schema::database_path;
+use std::time::Duration;
 
-type DbPool = Pool<SqliteConnectionManager>;
+pub type DbPool = Pool<SqliteConnectionManager>;
+
+/// How long a statement waits for a lock held by another connection (the API
+/// server or another pool member) before failing with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+
+/// Run on every connection the pool opens. WAL lets the API server read while
+/// the processor writes; the busy timeout makes writers queue instead of erroring.
+fn configure_connection(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(())
+}
 
 pub fn get_pool(db_name: &str) -> Result<DbPool, AppError> {
-    let manager = SqliteConnectionManager::file(db_name);
+    let manager = SqliteConnectionManager::file(db_name).with_init(configure_connection);
     Pool::builder()
         .max_size(5)
         .build(manager)
         .map_err(|e| AppError::R2D2Error(e))
 }
 
+/// Brings the schema up to date with the embedded migrations shared with the
+/// API server.
 pub fn initialize_database(pool: &DbPool) -> Result<(), AppError> {
-    let conn: PooledConnection<SqliteConnectionManager> =
+    let mut conn: PooledConnection<SqliteConnectionManager> =
         pool.get().map_err(|e| AppError::R2D2Error(e))?;
 
-    let sql_script = fs::read_to_string("database.sql").map_err(|e| AppError::IoError(e))?;
+    let applied = schema::migrate(&mut conn)?;
+    if !applied.is_empty() {
+        println!("Applied schema migrations {:?}", applied);
+    }
+    println!(
+        "Database initialized successfully (schema version {}).",
+        schema::latest_version()
+    );
+    Ok(())
+}
 
-    conn.execute_batch(&sql_script)
-        .map_err(|e| AppError::RusqliteError(e))?;
+const USAGE: &str = "Usage: test_v05 schema <command>
 
-    println!("Database initialized successfully.");
-    Ok(())
+Commands:
+  status    list embedded migrations and whether the database has applied them
+  migrate   apply pending migrations";
+
+/// Entry point for `test_v05 schema ...`.
+pub fn run(args: &[String]) -> Result<(), AppError> {
+    let pool = get_pool(&database_path())?;
+    match args.first().map(String::as_str) {
+        Some("status") => {
+            let conn = pool.get()?;
+            schema::print_status(&conn)?;
+            Ok(())
+        }
+        Some("migrate") => initialize_database(&pool),
+        Some(command) => Err(AppError::EventProcessingError(format!(
+            "unknown command: {}\n\n{}",
+            command, USAGE
+        ))),
+        None => Err(AppError::EventProcessingError(format!(
+            "missing command\n\n{}",
+            USAGE
+        ))),
+    }
 }
@@ -1,6 +1,10 @@
 // src/error.rs
+use crate::pipeline::PipelineError;
+use crate::rules::RuleError;
+use csv::Error as CsvError;
 use r2d2::Error as R2D2Error;
 use rusqlite::Error as RusqliteError;
+use schema::MigrationError;
 use sled::Error as SledError;
 use std::io::Error as IoError;
 
@@ -13,6 +17,10 @@ pub enum AppError {
     RusqliteError(RusqliteError),
     R2D2Error(R2D2Error),
     IoError(IoError),
+    CsvError(CsvError),
+    MigrationError(MigrationError),
+    RuleError(RuleError),
+    PipelineError(PipelineError),
 }
 
 impl std::error::Error for AppError {}
@@ -27,6 +35,10 @@ impl std::fmt::Display for AppError {
             AppError::RusqliteError(e) => write!(f, "Rusqlite error: {}", e),
             AppError::R2D2Error(e) => write!(f, "R2D2 error: {}", e),
             AppError::IoError(e) => write!(f, "IO error: {}", e),
+            AppError::CsvError(e) => write!(f, "CSV error: {}", e),
+            AppError::MigrationError(e) => write!(f, "{}", e),
+            AppError::RuleError(e) => write!(f, "{}", e),
+            AppError::PipelineError(e) => write!(f, "{}", e),
         }
     }
 }
@@ -54,3 +66,27 @@ impl From<IoError> for AppError {
         AppError::IoError(e)
     }
 }
+
+impl From<CsvError> for AppError {
+    fn from(e: CsvError) -> Self {
+        AppError::CsvError(e)
+    }
+}
+
+impl From<MigrationError> for AppError {
+    fn from(e: MigrationError) -> Self {
+        AppError::MigrationError(e)
+    }
+}
+
+impl From<RuleError> for AppError {
+    fn from(e: RuleError) -> Self {
+        AppError::RuleError(e)
+    }
+}
+
+impl From<PipelineError> for AppError {
+    fn from(e: PipelineError) -> Self {
+        AppError::PipelineError(e)
+    }
+}
@@ -11,6 +11,167 @@ const MAX_PORT: u16 = 32000;
 const START_TIME_INTERVAL_MINUTES: u8 = 2;
 const BUFFER_SIZE: usize = 10000;
 
+/// A vendor prefix (OUI) and how often it should be drawn relative to the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedOui {
+    pub oui: [u8; 3],
+    pub vendor: String,
+    pub weight: u32,
+}
+
+impl WeightedOui {
+    pub fn new(oui: [u8; 3], vendor: &str, weight: u32) -> Self {
+        WeightedOui {
+            oui,
+            vendor: vendor.to_string(),
+            weight,
+        }
+    }
+}
+
+/// Malformed or non-canonical renderings mixed into the MAC pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidMacForm {
+    /// A non-hex character in the first octet, e.g. `Z8:6F:B9:12:34:56`.
+    NonHex,
+    /// One octet too few or too many.
+    WrongLength,
+    /// `28-6F-B9-12-34-56`
+    Dashes,
+    /// `28.6F.B9.12.34.56`
+    Dots,
+    /// `28:6f:b9:12:34:56`
+    Lowercase,
+    /// `286f.b912.3456`
+    Cisco,
+    /// `28:6F-B9:12.34:56`
+    MixedSeparators,
+}
+
+impl InvalidMacForm {
+    pub const ALL: [InvalidMacForm; 7] = [
+        InvalidMacForm::NonHex,
+        InvalidMacForm::WrongLength,
+        InvalidMacForm::Dashes,
+        InvalidMacForm::Dots,
+        InvalidMacForm::Lowercase,
+        InvalidMacForm::Cisco,
+        InvalidMacForm::MixedSeparators,
+    ];
+
+    pub fn render(self, mac: [u8; 6], rng: &mut impl Rng) -> String {
+        let octets: Vec<String> = mac.iter().map(|octet| format!("{:02X}", octet)).collect();
+        match self {
+            InvalidMacForm::NonHex => format!("Z{}", &octets.join(":")[1..]),
+            InvalidMacForm::WrongLength => {
+                if rng.random_bool(0.5) {
+                    octets[..5].join(":")
+                } else {
+                    format!("{}:{:02X}", octets.join(":"), rng.random::<u8>())
+                }
+            }
+            InvalidMacForm::Dashes => octets.join("-"),
+            InvalidMacForm::Dots => octets.join("."),
+            InvalidMacForm::Lowercase => octets.join(":").to_lowercase(),
+            InvalidMacForm::Cisco => format!(
+                "{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}",
+                mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+            ),
+            InvalidMacForm::MixedSeparators => format!(
+                "{}:{}-{}:{}.{}:{}",
+                octets[0], octets[1], octets[2], octets[3], octets[4], octets[5]
+            ),
+        }
+    }
+}
+
+/// How the MAC pool of an `EventGenerator` is built. Each generator owns its
+/// config, so several tenants with different vendor mixes can run side by side.
+#[derive(Debug, Clone)]
+pub struct MacGeneratorConfig {
+    pub vendors: Vec<WeightedOui>,
+    /// Share of valid MACs with the locally administered bit set (random prefix).
+    pub locally_administered_ratio: f64,
+    /// Share of valid MACs that are IPv4 (`01:00:5E`) or IPv6 (`33:33`) multicast.
+    pub multicast_ratio: f64,
+    pub invalid_forms: Vec<InvalidMacForm>,
+}
+
+impl Default for MacGeneratorConfig {
+    fn default() -> Self {
+        MacGeneratorConfig {
+            vendors: vec![
+                WeightedOui::new([0x28, 0x6F, 0xB9], "Nokia", 20),
+                WeightedOui::new([0x00, 0x03, 0x93], "Apple", 15),
+                WeightedOui::new([0x00, 0x16, 0x32], "Samsung Electronics", 12),
+                WeightedOui::new([0x00, 0xE0, 0xFC], "Huawei Technologies", 10),
+                WeightedOui::new([0x00, 0x00, 0x0C], "Cisco Systems", 10),
+                WeightedOui::new([0x00, 0x1B, 0x21], "Intel Corporate", 8),
+                WeightedOui::new([0x3C, 0x5A, 0xB4], "Google", 6),
+                WeightedOui::new([0xF0, 0x9F, 0xC2], "Ubiquiti Networks", 5),
+                WeightedOui::new([0xB8, 0x27, 0xEB], "Raspberry Pi Foundation", 4),
+                WeightedOui::new([0x00, 0x50, 0x56], "VMware", 4),
+                WeightedOui::new([0x00, 0x15, 0x5D], "Microsoft", 3),
+                WeightedOui::new([0x00, 0x16, 0x3E], "Xensource", 3),
+            ],
+            locally_administered_ratio: 0.05,
+            multicast_ratio: 0.01,
+            invalid_forms: InvalidMacForm::ALL.to_vec(),
+        }
+    }
+}
+
+impl MacGeneratorConfig {
+    pub fn with_vendors(mut self, vendors: Vec<WeightedOui>) -> Self {
+        self.vendors = vendors;
+        self
+    }
+
+    pub fn with_locally_administered_ratio(mut self, ratio: f64) -> Self {
+        self.locally_administered_ratio = ratio;
+        self
+    }
+
+    pub fn with_multicast_ratio(mut self, ratio: f64) -> Self {
+        self.multicast_ratio = ratio;
+        self
+    }
+
+    pub fn with_invalid_forms(mut self, forms: Vec<InvalidMacForm>) -> Self {
+        self.invalid_forms = forms;
+        self
+    }
+
+    fn generate_mac(&self, rng: &mut impl Rng) -> [u8; 6] {
+        let mut mac: [u8; 6] = rng.random();
+        let draw = rng.random::<f64>();
+        if draw < self.multicast_ratio {
+            if rng.random_bool(0.5) {
+                mac[..3].copy_from_slice(&[0x01, 0x00, 0x5E]);
+                mac[3] &= 0x7F;
+            } else {
+                mac[..2].copy_from_slice(&[0x33, 0x33]);
+            }
+        } else if draw < self.multicast_ratio + self.locally_administered_ratio {
+            mac[0] = (mac[0] | 0x02) & !0x01;
+        } else if let Ok(vendor) = self.vendors.choose_weighted(rng, |vendor| vendor.weight) {
+            mac[..3].copy_from_slice(&vendor.oui);
+        } else {
+            // No usable vendor list: fall back to a random universally administered unicast prefix.
+            mac[0] &= !0x03;
+        }
+        mac
+    }
+
+    fn generate_invalid_mac(&self, rng: &mut impl Rng) -> String {
+        let mac = self.generate_mac(rng);
+        match self.invalid_forms.choose(rng) {
+            Some(form) => form.render(mac, rng),
+            None => InvalidMacForm::NonHex.render(mac, rng),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Event {
     mac_address: String,
@@ -28,8 +189,7 @@ pub struct EventGenerator {
     buffer: Vec<(DateTime<Utc>, Event)>,
 }
 
-fn generate_random_mac(rng: &mut impl Rng) -> String {
-    let mac: [u8; 6] = rng.random();
+fn format_mac(mac: [u8; 6]) -> String {
     format!(
         "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
         mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
@@ -79,19 +239,22 @@ fn generate_event(rng: &mut impl Rng, mac_addresses: &[String]) -> (Event, Event
 
 impl EventGenerator {
     pub async fn new(mac_size: usize, mac_invalid_size: usize) -> Self {
+        Self::with_config(mac_size, mac_invalid_size, MacGeneratorConfig::default()).await
+    }
+
+    pub async fn with_config(
+        mac_size: usize,
+        mac_invalid_size: usize,
+        config: MacGeneratorConfig,
+    ) -> Self {
         let mut rng = rand::rng();
 
         let mac_addresses_invalid: Vec<String> = (0..mac_invalid_size)
-            .map(|_| {
-                let mac = generate_random_mac(&mut rng);
-                mac.get(1..)
-                    .map(|rest| format!("Z{}", rest))
-                    .unwrap_or_else(|| "Z".to_string())
-            })
+            .map(|_| config.generate_invalid_mac(&mut rng))
             .collect();
 
         let mut mac_addresses: Vec<String> = (0..mac_size - mac_invalid_size)
-            .map(|_| generate_random_mac(&mut rng))
+            .map(|_| format_mac(config.generate_mac(&mut rng)))
             .collect();
 
         mac_addresses.extend(mac_addresses_invalid);
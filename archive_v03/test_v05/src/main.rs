@@ -1,69 +1,124 @@
 // src/main.rs
 
-use r2d2::{Pool, PooledConnection};
-use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, Error as RusqliteError};
 
+mod admin;
 mod database;
 mod error;
 mod generator;
+mod pipeline;
 mod processor;
-mod queue;
+mod quarantine;
+mod rules;
+mod stream;
+mod vendors;
 
-use database::{get_pool, initialize_database};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use broker::{Broker, Capacity, OverflowPolicy, Retention, Topic};
+use database::{database_path, get_pool, initialize_database, DbPool};
 use error::AppError;
 use generator::EventGenerator;
+use pipeline::PipelineSpec;
 use processor::EventProcessor;
-use queue::Queue;
+use quarantine::Quarantine;
+use rules::RuleSet;
 
 use std::{sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 
 const MAC_COUNT: usize = 10_000;
 const MAC_INV_COUNT: usize = 5;
+const EVENTS_TOPIC: &str = "events";
+const MAC_PROCESSOR_GROUP: &str = "mac_processor";
+const RETENTION_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const QUEUE_MAX_ITEMS: usize = 100_000;
+const QUEUE_MAX_BYTES: usize = 64 * 1024 * 1024;
+const WINDOW_RETENTION: Duration = Duration::from_secs(60 * 60);
+const WINDOW_MAX_ROWS: usize = 1_000_000;
+const WINDOW_SIZE: &str = "1 minute";
+const WINDOW_REPORT_EVERY: u64 = 10;
 
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("queue") {
+        if let Err(e) = admin::run(&args[2..]).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("quarantine") {
+        if let Err(e) = quarantine::run(&args[2..]).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("schema") {
+        if let Err(e) = database::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("vendors") {
+        if let Err(e) = vendors::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("Start");
 
-    let conn = get_pool("macs.db")?;
+    let pool = get_pool(&database_path())?;
 
-    initialize_database(&conn)?;
+    initialize_database(&pool)?;
 
-    let fields = vec![
-        "mac_address".to_string(),
-        "event_time".to_string(),
-        "ip_address_src".to_string(),
-        "port_src".to_string(),
-        "ip_address_dst".to_string(),
-        "port_dst".to_string(),
-        "event_type".to_string(),
-    ];
+    let rules = RuleSet::configured()?;
     let event_generator = EventGenerator::new(MAC_COUNT, MAC_INV_COUNT).await;
-    let event_processor = Arc::new(Mutex::new(EventProcessor::new(fields)));
-    let queue: Arc<Queue> = Arc::new(Queue::new("queue_db")?);
+    let broker = Broker::open("queue_db")?;
+    let mut event_processor = EventProcessor::new(rules, pool.clone())
+        .with_window(WINDOW_RETENTION, WINDOW_MAX_ROWS)
+        .with_quarantine(Quarantine::open(broker.db())?);
+    if let Ok(path) = std::env::var("EVENT_PIPELINE") {
+        let spec = PipelineSpec::load(&path)?;
+        println!("Running pipeline {} from {}", spec.name, path);
+        event_processor = event_processor.with_pipeline(spec).await?;
+    }
+    let event_processor = Arc::new(Mutex::new(event_processor));
+    let capacity = Capacity::default()
+        .with_max_items(QUEUE_MAX_ITEMS)
+        .with_max_bytes(QUEUE_MAX_BYTES)
+        .with_policy(OverflowPolicy::Block);
+    let topic: Topic = broker.topic_with_capacity(EVENTS_TOPIC, capacity)?;
+    let retention = Retention::default().with_max_age(RETENTION_MAX_AGE);
 
     // Producer Task
-    let producer_queue: Arc<Queue> = Arc::clone(&queue);
+    let producer_topic: Topic = topic.clone();
     tokio::spawn(async move {
         for event in event_generator {
-            if let Err(e) = producer_queue.push(&event).await {
+            if let Err(e) = producer_topic.publish(&event).await {
                 eprintln!("Error pushing event: {}", e);
             }
         }
     });
 
     // Consumer Task
-    let consumer_queue: Arc<Queue> = Arc::clone(&queue);
+    let consumer = topic.consumer(MAC_PROCESSOR_GROUP);
     let processor: Arc<Mutex<EventProcessor>> = Arc::clone(&event_processor);
     tokio::spawn(async move {
         loop {
-            match consumer_queue.pop().await {
-                Ok(Some(popped_event)) => {
+            match consumer.poll() {
+                Ok(Some(message)) => {
                     let mut processor = processor.lock().await;
-                    if let Err(e) = processor.process(popped_event).await {
+                    if let Err(e) = processor.process(message.value.clone()).await {
                         eprintln!("Error processing event: {}", e);
                     }
+                    if let Err(e) = consumer.commit(&message) {
+                        eprintln!("Error committing offset: {}", e);
+                    }
                 }
                 Ok(None) => {
                     // No event available, avoid busy-waiting
@@ -76,18 +131,35 @@ async fn main() -> Result<(), AppError> {
         }
     });
 
+    let mut ticks: u64 = 0;
     loop {
         tokio::time::sleep(Duration::from_secs(1)).await;
-        let count = get_macs_count(&conn)?;
-        println!("Macs count: {}", count);
+        ticks += 1;
+        let count = get_macs_count(&pool)?;
+        println!(
+            "Macs count: {} Queue depth: {} ({} bytes)",
+            count,
+            topic.len(),
+            topic.size_in_bytes()
+        );
+        if let Err(e) = topic.apply_retention(&retention) {
+            eprintln!("Error applying retention: {}", e);
+        }
+        if ticks.is_multiple_of(WINDOW_REPORT_EVERY) {
+            let summary = event_processor.lock().await.window_summary(WINDOW_SIZE).await;
+            match summary.and_then(|batches| Ok(pretty_format_batches(&batches)?)) {
+                Ok(table) => println!("Events per {}:\n{}", WINDOW_SIZE, table),
+                Err(e) => eprintln!("Error computing window summary: {}", e),
+            }
+        }
     }
 }
 
-fn get_macs_count(pool: &Pool<SqliteConnectionManager>) -> Result<i64, rusqlite::Error> {
+fn get_macs_count(pool: &DbPool) -> Result<i64, rusqlite::Error> {
     let conn = pool.get().map_err(|e| {
         rusqlite::Error::ToSqlConversionFailure(Box::new(e))
     })?;
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM mac_addresses")?;
+    let mut stmt = conn.prepare_cached("SELECT COUNT(*) FROM mac_addresses")?;
     let mut rows = stmt.query([])?;
     if let Some(row) = rows.next()? {
         let count: i64 = row.get(0)?;
@@ -0,0 +1,383 @@
+// src/pipeline.rs
+use crate::database::DbPool;
+use crate::stream::ProcessingContext;
+
+use datafusion::arrow::array::{Array, ArrayRef, Float64Array, Int64Array, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::error::DataFusionError;
+use deltalake::{protocol::SaveMode, DeltaOps};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection};
+use serde::Deserialize;
+use udf::write::dir_location;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PipelineError {
+    Io(std::io::Error),
+    Yaml(serde_yaml::Error),
+    DataFusion(DataFusionError),
+    Sqlite(rusqlite::Error),
+    R2D2(r2d2::Error),
+    Delta(deltalake::DeltaTableError),
+    Invalid(String),
+}
+
+impl std::error::Error for PipelineError {}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::Io(e) => write!(f, "Pipeline IO error: {}", e),
+            PipelineError::Yaml(e) => write!(f, "Invalid pipeline YAML: {}", e),
+            PipelineError::DataFusion(e) => write!(f, "Pipeline query error: {}", e),
+            PipelineError::Sqlite(e) => write!(f, "Pipeline SQLite error: {}", e),
+            PipelineError::R2D2(e) => write!(f, "Pipeline pool error: {}", e),
+            PipelineError::Delta(e) => write!(f, "Pipeline Delta error: {}", e),
+            PipelineError::Invalid(msg) => write!(f, "Invalid pipeline: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for PipelineError {
+    fn from(e: std::io::Error) -> Self {
+        PipelineError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for PipelineError {
+    fn from(e: serde_yaml::Error) -> Self {
+        PipelineError::Yaml(e)
+    }
+}
+
+impl From<DataFusionError> for PipelineError {
+    fn from(e: DataFusionError) -> Self {
+        PipelineError::DataFusion(e)
+    }
+}
+
+impl From<datafusion::arrow::error::ArrowError> for PipelineError {
+    fn from(e: datafusion::arrow::error::ArrowError) -> Self {
+        PipelineError::DataFusion(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for PipelineError {
+    fn from(e: rusqlite::Error) -> Self {
+        PipelineError::Sqlite(e)
+    }
+}
+
+impl From<r2d2::Error> for PipelineError {
+    fn from(e: r2d2::Error) -> Self {
+        PipelineError::R2D2(e)
+    }
+}
+
+impl From<deltalake::DeltaTableError> for PipelineError {
+    fn from(e: deltalake::DeltaTableError) -> Self {
+        PipelineError::Delta(e)
+    }
+}
+
+/// A named SQL statement. It may read `mac_table` (the current batch),
+/// `events` (the retained window) or any earlier step.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StepSpec {
+    pub name: String,
+    pub sql: String,
+}
+
+/// Where the rows of the final step go after every batch.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkSpec {
+    /// Upserts into `table`, created on first use with `key` as primary key.
+    Sqlite { table: String, key: Vec<String> },
+    /// Appends a Parquet file per batch under the directory `path`.
+    Parquet {
+        path: String,
+        #[serde(default)]
+        partition_by: Vec<String>,
+    },
+    /// Appends to the Delta table at `path`, creating it on first use.
+    Delta {
+        path: String,
+        #[serde(default)]
+        partition_by: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineSpec {
+    pub name: String,
+    pub steps: Vec<StepSpec>,
+    pub sinks: Vec<SinkSpec>,
+}
+
+impl PipelineSpec {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PipelineError> {
+        Self::from_yaml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, PipelineError> {
+        let spec: PipelineSpec = serde_yaml::from_str(yaml)?;
+        if spec.steps.is_empty() {
+            return Err(PipelineError::Invalid(format!(
+                "{} has no steps",
+                spec.name
+            )));
+        }
+        let mut names = HashSet::new();
+        for step in &spec.steps {
+            if !names.insert(step.name.as_str()) {
+                return Err(PipelineError::Invalid(format!(
+                    "{}: step {} is defined twice",
+                    spec.name, step.name
+                )));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// A pipeline whose steps are registered as views of a `ProcessingContext`.
+/// Views are planned once; running the pipeline only executes the last one.
+pub struct Pipeline {
+    name: String,
+    output: String,
+    sinks: Vec<SinkSpec>,
+}
+
+impl Pipeline {
+    /// Registers every step as a view, failing on the first step that does
+    /// not plan, e.g. because it names an unknown table or column.
+    pub async fn compile(
+        spec: PipelineSpec,
+        context: &ProcessingContext,
+    ) -> Result<Self, PipelineError> {
+        let ctx = context.session();
+        for step in &spec.steps {
+            let view = ctx
+                .sql(&step.sql)
+                .await
+                .map_err(|e| {
+                    PipelineError::Invalid(format!("{}: step {}: {}", spec.name, step.name, e))
+                })?
+                .into_view();
+            ctx.register_table(step.name.as_str(), view)?;
+        }
+        let output = spec
+            .steps
+            .last()
+            .map(|step| step.name.clone())
+            .unwrap_or_default();
+        Ok(Pipeline {
+            name: spec.name,
+            output,
+            sinks: spec.sinks,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Runs the final step against the current contents of the context and
+    /// hands its rows to every sink. Returns the number of rows produced.
+    pub async fn run(
+        &self,
+        context: &ProcessingContext,
+        pool: &DbPool,
+    ) -> Result<usize, PipelineError> {
+        let df = context.session().table(self.output.as_str()).await?;
+        let batches = df.collect().await?;
+        let rows = batches.iter().map(RecordBatch::num_rows).sum();
+        if rows == 0 {
+            return Ok(0);
+        }
+
+        for sink in &self.sinks {
+            match sink {
+                SinkSpec::Sqlite { table, key } => {
+                    let mut conn = pool.get()?;
+                    upsert(&mut conn, table, key, &batches)?;
+                }
+                SinkSpec::Parquet { path, partition_by } => {
+                    std::fs::create_dir_all(path)?;
+                    let dir = dir_location(Path::new(path));
+                    let options =
+                        DataFrameWriteOptions::new().with_partition_by(partition_by.clone());
+                    context
+                        .session()
+                        .read_batches(batches.clone())?
+                        .write_parquet(&dir, options, None)
+                        .await?;
+                }
+                SinkSpec::Delta { path, partition_by } => {
+                    std::fs::create_dir_all(path)?;
+                    let mut write = DeltaOps::try_from_uri(path)
+                        .await?
+                        .write(batches.clone())
+                        .with_save_mode(SaveMode::Append);
+                    if !partition_by.is_empty() {
+                        write = write.with_partition_columns(partition_by.clone());
+                    }
+                    write.await?;
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// `INSERT ... ON CONFLICT (key) DO UPDATE` for every row, in one transaction.
+fn upsert(
+    conn: &mut Connection,
+    table: &str,
+    key: &[String],
+    batches: &[RecordBatch],
+) -> Result<(), PipelineError> {
+    let Some(schema) = batches.first().map(RecordBatch::schema) else {
+        return Ok(());
+    };
+    for column in key {
+        if schema.index_of(column).is_err() {
+            return Err(PipelineError::Invalid(format!(
+                "sqlite sink {}: key column {} is not produced by the pipeline",
+                table, column
+            )));
+        }
+    }
+    let columns: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|field| quote_ident(field.name()))
+        .collect();
+    let keys: Vec<String> = key.iter().map(|column| quote_ident(column)).collect();
+
+    conn.execute_batch(&create_table_sql(table, &schema, &keys))?;
+
+    let updates: Vec<String> = columns
+        .iter()
+        .filter(|column| !keys.contains(column))
+        .map(|column| format!("{0} = excluded.{0}", column))
+        .collect();
+    let on_conflict = if updates.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!("DO UPDATE SET {}", updates.join(", "))
+    };
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) {}",
+        quote_ident(table),
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", "),
+        keys.join(", "),
+        on_conflict
+    );
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(&sql)?;
+        for batch in batches {
+            let values = batch
+                .columns()
+                .iter()
+                .map(sql_values)
+                .collect::<Result<Vec<_>, _>>()?;
+            for row in 0..batch.num_rows() {
+                stmt.execute(params_from_iter(values.iter().map(|column| &column[row])))?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn create_table_sql(table: &str, schema: &Schema, keys: &[String]) -> String {
+    let columns: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            format!(
+                "{} {}",
+                quote_ident(field.name()),
+                sqlite_type(field.data_type())
+            )
+        })
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({}, PRIMARY KEY ({}))",
+        quote_ident(table),
+        columns.join(", "),
+        keys.join(", ")
+    )
+}
+
+fn sqlite_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => "INTEGER",
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => "REAL",
+        _ => "TEXT",
+    }
+}
+
+/// One column as SQLite values. Types without a SQLite counterpart, such as
+/// timestamps, are stored in their Arrow string form.
+fn sql_values(array: &ArrayRef) -> Result<Vec<SqlValue>, PipelineError> {
+    let values = match sqlite_type(array.data_type()) {
+        "INTEGER" => {
+            let array = cast(array, &DataType::Int64)?;
+            let array = array.as_any().downcast_ref::<Int64Array>().unwrap();
+            (0..array.len())
+                .map(|i| match array.is_null(i) {
+                    true => SqlValue::Null,
+                    false => SqlValue::Integer(array.value(i)),
+                })
+                .collect()
+        }
+        "REAL" => {
+            let array = cast(array, &DataType::Float64)?;
+            let array = array.as_any().downcast_ref::<Float64Array>().unwrap();
+            (0..array.len())
+                .map(|i| match array.is_null(i) {
+                    true => SqlValue::Null,
+                    false => SqlValue::Real(array.value(i)),
+                })
+                .collect()
+        }
+        _ => {
+            let array = cast(array, &DataType::Utf8)?;
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            (0..array.len())
+                .map(|i| match array.is_null(i) {
+                    true => SqlValue::Null,
+                    false => SqlValue::Text(array.value(i).to_string()),
+                })
+                .collect()
+        }
+    };
+    Ok(values)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
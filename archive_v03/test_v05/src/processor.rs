@@ -1,7 +1,10 @@
-use crate::database::*;
+use crate::database::DbPool;
+use crate::pipeline::{Pipeline, PipelineError, PipelineSpec};
+use crate::quarantine::{Quarantine, Violation};
+use crate::rules::RuleSet;
+use crate::stream::{ProcessingContext, LATEST_TABLE, WINDOW_TABLE};
+use crate::vendors::VendorRegistry;
 
-use r2d2::{Pool, PooledConnection};
-use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{params, Connection};
 
 use datafusion::arrow::{
@@ -10,25 +13,39 @@ use datafusion::arrow::{
     record_batch::RecordBatch,
 };
 use datafusion::dataframe::DataFrameWriteOptions;
-use datafusion::datasource::memory::MemTable;
 use datafusion::error::{DataFusionError, Result};
-use datafusion::execution::context::SessionContext;
 use datafusion::prelude::*;
 
 use serde_json::Value;
+use udf::mac::MacAddress;
 use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::sync::Arc;
-const BATCH_SIZE: usize = 100;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+pub const BATCH_SIZE: usize = 100;
+
+/// How long a loaded vendor registry is used before it is read again, so
+/// vendors imported while the processor runs are picked up.
+const VENDOR_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
 
 pub struct EventProcessor {
     fields: Vec<String>,
+    rules: RuleSet,
     schema: Arc<Schema>,
+    context: ProcessingContext,
     batch: VecDeque<Value>,
+    vendors: Mutex<Option<(Instant, Arc<VendorRegistry>)>>,
+    pool: DbPool,
+    quarantine: Option<Quarantine>,
+    pipeline: Option<Pipeline>,
 }
 
 impl EventProcessor {
-    pub fn new(fields: Vec<String>) -> Self {
+    /// Every field declared in `rules` becomes a column of the record batches.
+    /// `pool` is shared with the rest of the program; each batch borrows one
+    /// connection from it.
+    pub fn new(rules: RuleSet, pool: DbPool) -> Self {
+        let fields = rules.field_names();
         let schema = Arc::new(Schema::new(
             fields
                 .iter()
@@ -37,11 +54,38 @@ impl EventProcessor {
         ));
         EventProcessor {
             fields: fields,
+            rules: rules,
+            context: ProcessingContext::new(schema.clone()),
             schema: schema,
             batch: VecDeque::new(),
+            vendors: Mutex::new(None),
+            pool,
+            quarantine: None,
+            pipeline: None,
         }
     }
 
+    /// Keeps accepted events for `retention`, at most `max_rows` of them, for
+    /// `window_summary` and other queries over the `events` table.
+    pub fn with_window(mut self, retention: Duration, max_rows: usize) -> Self {
+        self.context = ProcessingContext::with_retention(self.schema.clone(), retention, max_rows);
+        self
+    }
+
+    /// Registers the steps of `spec` as views over `mac_table` and `events` and
+    /// runs the pipeline after every batch. Call after `with_window`, which
+    /// replaces the context the views live in.
+    pub async fn with_pipeline(mut self, spec: PipelineSpec) -> Result<Self, PipelineError> {
+        self.pipeline = Some(Pipeline::compile(spec, &self.context).await?);
+        Ok(self)
+    }
+
+    /// Keeps rejected events in `quarantine` instead of only logging them.
+    pub fn with_quarantine(mut self, quarantine: Quarantine) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
     pub async fn process(&mut self, event: Value) -> Result<(), Box<dyn Error>> {
         // Add the event to the batch
         self.batch.push_back(event.clone());
@@ -56,6 +100,24 @@ impl EventProcessor {
     }
 
     pub async fn process_batch(&self, events: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        let discarded_events = self.write_batch(events).await?;
+        if let Some(quarantine) = &self.quarantine {
+            quarantine.add_many(discarded_events)?;
+        } else {
+            for (event, violations) in &discarded_events {
+                let reasons: Vec<String> = violations.iter().map(Violation::to_string).collect();
+                eprintln!("Discarded event ({}): {}", reasons.join("; "), event);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the valid events of the batch and returns the rejected ones
+    /// once the write has been committed, so a failed batch rejects nothing.
+    pub async fn write_batch(
+        &self,
+        events: Vec<Value>,
+    ) -> Result<Vec<(Value, Vec<Violation>)>, Box<dyn Error>> {
         println!("Processing batch of size: {}", events.len());
 
         let (extracted_data, discarded_events) = self.validate_events(events);
@@ -67,23 +129,59 @@ impl EventProcessor {
         );
 
         let record_batch = self.create_record_batch(&extracted_data)?;
-        let table = MemTable::try_new(self.schema.clone(), vec![vec![record_batch]])?;
-        let ctx = SessionContext::new();
-        ctx.register_table("mac_table", Arc::new(table))?;
+        self.context.append(record_batch);
 
-        self.execute_query(ctx).await?;
+        self.execute_query().await?;
 
-        Ok(())
+        if let Some(pipeline) = &self.pipeline {
+            let rows = pipeline.run(&self.context, &self.pool).await?;
+            println!("Pipeline {} wrote {} rows", pipeline.name(), rows);
+        }
+
+        Ok(discarded_events)
     }
 
-    async fn execute_query(&self,  ctx: SessionContext) -> Result<(), Box<dyn Error>> {
-        let df = ctx.sql("SELECT * FROM mac_table").await?;
+    /// The vendor registry, read again once it is older than
+    /// `VENDOR_REFRESH_INTERVAL`. A failed load is not cached, so the next
+    /// batch retries.
+    fn vendor_registry(&self, conn: &Connection) -> Result<Arc<VendorRegistry>, rusqlite::Error> {
+        let mut vendors = self.vendors.lock().unwrap();
+        if let Some((loaded_at, registry)) = vendors.as_ref() {
+            if loaded_at.elapsed() < VENDOR_REFRESH_INTERVAL {
+                return Ok(registry.clone());
+            }
+        }
+        let registry = Arc::new(VendorRegistry::load(conn)?);
+        println!("Loaded {} vendor prefixes", registry.len());
+        *vendors = Some((Instant::now(), registry.clone()));
+        Ok(registry)
+    }
+
+    /// Events per `window` (e.g. `1 minute`) and event type over every batch
+    /// still retained, not just the last one.
+    pub async fn window_summary(&self, window: &str) -> Result<Vec<RecordBatch>> {
+        let query = format!(
+            "SELECT date_bin(INTERVAL '{}', CAST(event_time AS TIMESTAMP)) AS window_start, \
+                    event_type, count(*) AS events, count(DISTINCT mac_address) AS macs \
+             FROM {} GROUP BY 1, 2 ORDER BY 1, 2",
+            window, WINDOW_TABLE
+        );
+        self.context.sql(&query).await?.collect().await
+    }
 
-        let df = ctx
-            .sql("SELECT mac_address,max(event_time) as event_time FROM mac_table GROUP BY mac_address")
+    /// Upserts the MACs of the batch that was just appended.
+    async fn execute_query(&self) -> Result<(), Box<dyn Error>> {
+        let df = self
+            .context
+            .sql(&format!(
+                "SELECT mac_address,max(event_time) as event_time FROM {} GROUP BY mac_address",
+                LATEST_TABLE
+            ))
             .await?;
         // df.clone().show().await?;
         let batches = df.collect().await?;
+        let mut conn = self.pool.get()?;
+        let vendors = self.vendor_registry(&conn)?;
         for batch in batches {
             let mac_address_col = batch
                 .column(0)
@@ -100,27 +198,36 @@ impl EventProcessor {
                     DataFusionError::Internal("Failed to cast event_time column".to_string())
                 })?;
 
-            let mut conn = get_pool("macs.db")?.get()?;
             let tx = conn.transaction()?;
+            // Blocks missing from the IEEE registry get a placeholder row without org_name.
             let vendor_stmt_text =
-                "INSERT OR REPLACE INTO mac_vendors (id, designation) VALUES (?, ?)";
-            let mut vendor_stmt = tx.prepare(vendor_stmt_text)?;
+                "INSERT OR IGNORE INTO mac_vendors (id, designation) VALUES (?, ?)";
+            let mut vendor_stmt = tx.prepare_cached(vendor_stmt_text)?;
 
             let mac_stmt_text =
                 "INSERT OR REPLACE INTO mac_addresses  (id, mac_address , mac_vendor_id ) VALUES (?, ?, ?)";
-            let mut mac_stmt = tx.prepare(mac_stmt_text)?;
+            let mut mac_stmt = tx.prepare_cached(mac_stmt_text)?;
 
             for i in 0..batch.num_rows() {
                 let mac_address = mac_address_col.value(i);
                 let event_time = event_time_col.value(i);
-                let mac_address_id = get_mac_id(mac_address);
-                if let Some((vendor_id, vendor_design)) = get_vendor_info(mac_address) {
-                    vendor_stmt.execute(params![vendor_id, vendor_design])?;
-                    mac_stmt.execute(params![mac_address_id, mac_address, vendor_id])?;
-                    // println!("MAC {} Time {}", mac_address, event_time);
-                } else {
-                    println!("No vendor info found for MAC {}", mac_address);
-                }
+                let mac: MacAddress = match mac_address.parse() {
+                    Ok(mac) => mac,
+                    Err(e) => {
+                        println!("Skipping MAC {}: {}", mac_address, e);
+                        continue;
+                    }
+                };
+                let vendor_id = match vendors.lookup(&mac) {
+                    Some(vendor) => Some(vendor.id),
+                    None if mac.is_locally_administered() => None,
+                    None => {
+                        vendor_stmt.execute(params![mac.oui_id(), mac.oui_string()])?;
+                        Some(mac.oui_id() as i64)
+                    }
+                };
+                mac_stmt.execute(params![mac.to_u64() as i64, mac_address, vendor_id])?;
+                // println!("MAC {} Time {}", mac_address, event_time);
             }
             drop(vendor_stmt);
             drop(mac_stmt);
@@ -129,43 +236,33 @@ impl EventProcessor {
         Ok(())
     }
 
-    fn validate_events(&self, events: Vec<Value>) -> (HashMap<&str, Vec<String>>, Vec<Value>) {
+    fn validate_events(
+        &self,
+        events: Vec<Value>,
+    ) -> (HashMap<&str, Vec<String>>, Vec<(Value, Vec<Violation>)>) {
         let mut extracted_data: HashMap<&str, Vec<String>> = self
             .fields
             .iter()
             .map(|field| (field.as_str(), Vec::new()))
             .collect();
 
-        let mut discarded_events: Vec<Value> = Vec::new();
+        let mut discarded_events: Vec<(Value, Vec<Violation>)> = Vec::new();
 
         for event in events {
-            let mut temp_values: HashMap<&str, String> = HashMap::new();
-            let mut valid = true;
-
-            for field in &self.fields {
-                if let Some(value) = event.get(field).and_then(|v| v.as_str()) {
-                    temp_values.insert(field.as_str(), value.to_string());
-                } else {
-                    valid = false;
-                    break; // If any field is missing, discard the event
-                }
-            }
-
-            if valid {
-                // Push all values to extracted_data, ensuring we only push complete events
-                for field in &self.fields {
-                    extracted_data
-                        .get_mut(field.as_str())
-                        .unwrap()
-                        .push(temp_values[field.as_str()].clone());
+            match self.rules.validate(&event) {
+                Ok(values) => {
+                    // Push all values to extracted_data, ensuring we only push complete events
+                    for (field, value) in self.fields.iter().zip(values) {
+                        extracted_data.get_mut(field.as_str()).unwrap().push(value);
+                    }
                 }
-            } else {
-                discarded_events.push(event);
+                Err(violations) => discarded_events.push((event, violations)),
             }
         }
 
         (extracted_data, discarded_events)
     }
+
     fn create_record_batch(
         &self,
         extracted_data: &HashMap<&str, Vec<String>>,
@@ -179,20 +276,3 @@ impl EventProcessor {
         RecordBatch::try_new(self.schema.clone(), arrays)
     }
 }
-
-fn get_mac_id(mac: &str) -> Option<u64> {
-    let mac_clean = mac.replace(":", "");
-    u64::from_str_radix(&mac_clean, 16).ok()
-}
-
-fn get_vendor_info(mac: &str) -> Option<(u64, String)> {
-    let parts: Vec<&str> = mac.split(':').collect();
-    if parts.len() >= 3 {
-        let oui = format!("{}{}{}", parts[0], parts[1], parts[2]);
-        u64::from_str_radix(&oui, 16)
-            .ok()
-            .map(|vendor_id| (vendor_id, oui))
-    } else {
-        None
-    }
-}
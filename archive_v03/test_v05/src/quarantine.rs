@@ -0,0 +1,236 @@
+// src/quarantine.rs
+use crate::database::{database_path, get_pool};
+use crate::error::AppError;
+use crate::processor::{EventProcessor, BATCH_SIZE};
+use crate::rules::RuleSet;
+
+use broker::{now_millis, to_sled_error, Broker};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sled::{Batch, Db, Tree};
+use std::fmt;
+
+pub const QUARANTINE_TREE: &str = "quarantine";
+
+const DEFAULT_QUEUE_PATH: &str = "queue_db";
+const DEFAULT_LIST_COUNT: usize = 10;
+
+const USAGE: &str = "Usage: test_v05 quarantine [--db <path>] <command>
+
+Commands:
+  list [n]    print the first n quarantined events with the reason they were rejected
+  replay      run quarantined events through the processor again; events that
+              still fail are quarantined with their new reason
+  purge       remove all quarantined events";
+
+/// One broken rule: the field, the kind of rule (`required`, `type`, `range`,
+/// `regex`, `enum` or `cross_field`) and a readable reason.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Violation {
+    pub field: String,
+    pub rule: String,
+    pub reason: String,
+}
+
+impl Violation {
+    pub fn new(
+        field: impl Into<String>,
+        rule: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Violation {
+            field: field.into(),
+            rule: rule.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}] {}", self.field, self.rule, self.reason)
+    }
+}
+
+/// A rejected event, stored exactly as it was received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    pub violations: Vec<Violation>,
+    pub event: Value,
+}
+
+/// Rejected events kept in the broker's sled database, keyed by a
+/// monotonically increasing id so they replay in arrival order.
+#[derive(Clone)]
+pub struct Quarantine {
+    db: Db,
+    events: Tree,
+}
+
+impl Quarantine {
+    /// Opens the quarantine kept in `db`, usually the broker's database.
+    pub fn open(db: &Db) -> sled::Result<Self> {
+        Ok(Quarantine {
+            db: db.clone(),
+            events: db.open_tree(QUARANTINE_TREE)?,
+        })
+    }
+
+    /// Stores every rejected event in one batch and returns how many were stored.
+    pub fn add_many(&self, rejected: Vec<(Value, Vec<Violation>)>) -> sled::Result<usize> {
+        self.replace_many(&[], rejected)
+    }
+
+    /// Removes `ids` and stores `rejected` in the same batch, so replayed
+    /// events are never lost or kept twice.
+    pub fn replace_many(
+        &self,
+        ids: &[u64],
+        rejected: Vec<(Value, Vec<Violation>)>,
+    ) -> sled::Result<usize> {
+        let timestamp = now_millis();
+        let mut batch = Batch::default();
+        for id in ids {
+            batch.remove(&id.to_be_bytes());
+        }
+        let count = rejected.len();
+        for (event, violations) in rejected {
+            let id = self.db.generate_id()?;
+            let record = QuarantinedEvent {
+                id,
+                timestamp,
+                violations,
+                event,
+            };
+            let value = serde_json::to_vec(&record).map_err(to_sled_error)?;
+            batch.insert(&id.to_be_bytes(), value);
+        }
+        self.events.apply_batch(batch)?;
+        Ok(count)
+    }
+
+    /// The oldest `count` quarantined events.
+    pub fn list(&self, count: usize) -> sled::Result<Vec<QuarantinedEvent>> {
+        self.events
+            .iter()
+            .values()
+            .take(count)
+            .map(|value| serde_json::from_slice(&value?).map_err(to_sled_error))
+            .collect()
+    }
+
+    pub fn last_id(&self) -> sled::Result<Option<u64>> {
+        Ok(self
+            .events
+            .last()?
+            .map(|(key, _)| u64::from_be_bytes(key.as_ref().try_into().unwrap())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn clear(&self) -> sled::Result<usize> {
+        let removed = self.events.len();
+        self.events.clear()?;
+        Ok(removed)
+    }
+}
+
+/// Entry point for `test_v05 quarantine ...`.
+pub async fn run(args: &[String]) -> Result<(), AppError> {
+    let mut path = DEFAULT_QUEUE_PATH.to_string();
+    let mut args = args.to_vec();
+    if let Some(pos) = args.iter().position(|arg| arg == "--db") {
+        args.remove(pos);
+        if pos >= args.len() {
+            return Err(usage_error("--db needs a path"));
+        }
+        path = args.remove(pos);
+    }
+
+    let broker = Broker::open(&path)?;
+    let quarantine = Quarantine::open(broker.db())?;
+    let command = args.first().map(String::as_str).unwrap_or("list");
+
+    match command {
+        "list" => {
+            let count = match args.get(1) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| usage_error(&format!("invalid count: {}", count)))?,
+                None => DEFAULT_LIST_COUNT,
+            };
+            println!("{} quarantined events", quarantine.len());
+            for record in quarantine.list(count)? {
+                println!("{:>8} {}", record.id, record.event);
+                for violation in &record.violations {
+                    println!("         {}", violation);
+                }
+            }
+            Ok(())
+        }
+        "replay" => {
+            let replayed = replay(&quarantine).await?;
+            println!(
+                "Replayed {} events, {} still quarantined",
+                replayed,
+                quarantine.len()
+            );
+            broker.flush().await?;
+            Ok(())
+        }
+        "purge" => {
+            let removed = quarantine.clear()?;
+            println!("Purged {} quarantined events", removed);
+            broker.flush().await?;
+            Ok(())
+        }
+        _ => Err(usage_error(&format!("unknown command: {}", command))),
+    }
+}
+
+/// Feeds quarantined events back through the processor. A batch is removed,
+/// and its new rejects quarantined, only after the processor committed it, so
+/// a failing database leaves the quarantine as it was.
+async fn replay(quarantine: &Quarantine) -> Result<usize, AppError> {
+    let pool = get_pool(&database_path())?;
+    let rules = RuleSet::configured()?;
+    let processor = EventProcessor::new(rules, pool);
+
+    // Rejects are appended behind the events being replayed, so stop at the
+    // last id that was quarantined before the replay started.
+    let last = match quarantine.last_id()? {
+        Some(id) => id,
+        None => return Ok(0),
+    };
+    let mut replayed = 0;
+    loop {
+        let records: Vec<QuarantinedEvent> = quarantine
+            .list(BATCH_SIZE)?
+            .into_iter()
+            .filter(|record| record.id <= last)
+            .collect();
+        if records.is_empty() {
+            return Ok(replayed);
+        }
+        let ids: Vec<u64> = records.iter().map(|record| record.id).collect();
+        let events = records.into_iter().map(|record| record.event).collect();
+        let rejected = processor
+            .write_batch(events)
+            .await
+            .map_err(|e| AppError::EventProcessingError(e.to_string()))?;
+        quarantine.replace_many(&ids, rejected)?;
+        replayed += ids.len();
+    }
+}
+
+fn usage_error(msg: &str) -> AppError {
+    AppError::QueueError(format!("{}\n\n{}", msg, USAGE))
+}
@@ -0,0 +1,451 @@
+// src/rules.rs
+use crate::quarantine::Violation;
+
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use udf::mac::MacAddress;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Rules used when `EVENT_RULES` does not name a file.
+const DEFAULT_RULES: &str = include_str!("../rules.yaml");
+
+/// Fields the processor reads from every event by name.
+pub const REQUIRED_FIELDS: [&str; 2] = ["mac_address", "event_time"];
+
+#[derive(Debug)]
+pub enum RuleError {
+    Io(std::io::Error),
+    Yaml(serde_yaml::Error),
+    Regex { field: String, error: regex::Error },
+    Invalid(String),
+}
+
+impl std::error::Error for RuleError {}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::Io(e) => write!(f, "Cannot read rules: {}", e),
+            RuleError::Yaml(e) => write!(f, "Invalid rules YAML: {}", e),
+            RuleError::Regex { field, error } => {
+                write!(f, "Invalid regex for {}: {}", field, error)
+            }
+            RuleError::Invalid(msg) => write!(f, "Invalid rules: {}", msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for RuleError {
+    fn from(e: std::io::Error) -> Self {
+        RuleError::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for RuleError {
+    fn from(e: serde_yaml::Error) -> Self {
+        RuleError::Yaml(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    String,
+    Int,
+    Ip,
+    Mac,
+    Rfc3339,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum CompareOp {
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = ">")]
+    Gt,
+}
+
+impl CompareOp {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Ge => ordering.is_ge(),
+            CompareOp::Gt => ordering.is_gt(),
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Ge => ">=",
+            CompareOp::Gt => ">",
+        }
+    }
+}
+
+/// One entry under `fields:` as written in the YAML file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    #[serde(default = "default_required")]
+    pub required: bool,
+    #[serde(default, rename = "type")]
+    pub field_type: FieldType,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub regex: Option<String>,
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<String>>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrossFieldSpec {
+    pub left: String,
+    pub op: CompareOp,
+    pub right: String,
+    /// Needed only when neither side is declared under `fields`.
+    #[serde(default, rename = "type")]
+    pub field_type: Option<FieldType>,
+}
+
+/// The YAML document. `fields` keeps the file's order, which is the column
+/// order of the processor's record batches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesSpec {
+    pub fields: serde_yaml::Mapping,
+    #[serde(default)]
+    pub cross_field: Vec<CrossFieldSpec>,
+}
+
+struct FieldRule {
+    name: String,
+    required: bool,
+    field_type: FieldType,
+    min: Option<i64>,
+    max: Option<i64>,
+    regex: Option<Regex>,
+    allowed: Option<Vec<String>>,
+}
+
+struct CrossFieldRule {
+    left: String,
+    op: CompareOp,
+    right: String,
+    field_type: FieldType,
+}
+
+/// Field rules compiled once, regexes included, and applied to every event.
+pub struct RuleSet {
+    fields: Vec<FieldRule>,
+    cross_field: Vec<CrossFieldRule>,
+}
+
+/// A value parsed according to its field type, so that cross-field rules
+/// compare numbers and instants rather than text.
+#[derive(PartialEq, PartialOrd)]
+enum Typed {
+    Text(String),
+    Int(i64),
+    Ip(IpAddr),
+    Mac(MacAddress),
+    Time(DateTime<FixedOffset>),
+}
+
+impl RuleSet {
+    /// Rules from `EVENT_RULES` if set, otherwise the built-in `rules.yaml`.
+    pub fn configured() -> Result<Self, RuleError> {
+        match std::env::var("EVENT_RULES") {
+            Ok(path) => Self::load(path),
+            Err(_) => Self::from_yaml(DEFAULT_RULES),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RuleError> {
+        Self::from_yaml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, RuleError> {
+        Self::compile(serde_yaml::from_str(yaml)?)
+    }
+
+    pub fn compile(spec: RulesSpec) -> Result<Self, RuleError> {
+        let mut fields = Vec::with_capacity(spec.fields.len());
+        for (name, field) in spec.fields {
+            let name = name
+                .as_str()
+                .ok_or_else(|| {
+                    RuleError::Invalid(format!("field name {:?} is not a string", name))
+                })?
+                .to_string();
+            let field: FieldSpec = serde_yaml::from_value(field)?;
+            if (field.min.is_some() || field.max.is_some()) && field.field_type != FieldType::Int {
+                return Err(RuleError::Invalid(format!(
+                    "{}: min and max need type int",
+                    name
+                )));
+            }
+            let regex = match &field.regex {
+                // Anchored so the pattern has to match the whole value.
+                Some(pattern) => {
+                    Some(Regex::new(&format!("^(?:{})$", pattern)).map_err(|error| {
+                        RuleError::Regex {
+                            field: name.clone(),
+                            error,
+                        }
+                    })?)
+                }
+                None => None,
+            };
+            fields.push(FieldRule {
+                name,
+                required: field.required,
+                field_type: field.field_type,
+                min: field.min,
+                max: field.max,
+                regex,
+                allowed: field.allowed,
+            });
+        }
+
+        for name in REQUIRED_FIELDS {
+            match fields.iter().find(|field| field.name == name) {
+                Some(field) if field.required => {}
+                Some(_) => {
+                    return Err(RuleError::Invalid(format!(
+                        "{} cannot be optional, the processor reads it from every event",
+                        name
+                    )))
+                }
+                None => {
+                    return Err(RuleError::Invalid(format!(
+                        "{} is not declared, the processor reads it from every event",
+                        name
+                    )))
+                }
+            }
+        }
+
+        let type_of = |name: &str| {
+            fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|field| field.field_type)
+        };
+        let mut cross_field = Vec::with_capacity(spec.cross_field.len());
+        for rule in spec.cross_field {
+            let declared = type_of(&rule.left).or_else(|| type_of(&rule.right));
+            let field_type = rule.field_type.or(declared).unwrap_or_default();
+            for side in [&rule.left, &rule.right] {
+                if type_of(side).is_some_and(|side_type| side_type != field_type) {
+                    return Err(RuleError::Invalid(format!(
+                        "{} {} {} compares fields of different types",
+                        rule.left,
+                        rule.op.symbol(),
+                        rule.right
+                    )));
+                }
+            }
+            cross_field.push(CrossFieldRule {
+                left: rule.left,
+                op: rule.op,
+                right: rule.right,
+                field_type,
+            });
+        }
+
+        Ok(RuleSet {
+            fields,
+            cross_field,
+        })
+    }
+
+    /// Names of the declared fields, in column order.
+    pub fn field_names(&self) -> Vec<String> {
+        self.fields.iter().map(|field| field.name.clone()).collect()
+    }
+
+    /// Returns the event's values in field order, with MAC addresses in
+    /// canonical form and absent optional fields as empty strings, or every
+    /// rule the event breaks.
+    pub fn validate(&self, event: &Value) -> Result<Vec<String>, Vec<Violation>> {
+        let mut values = Vec::with_capacity(self.fields.len());
+        let mut violations = Vec::new();
+
+        for field in &self.fields {
+            let raw = match event.get(&field.name) {
+                None | Some(Value::Null) => {
+                    if field.required {
+                        violations.push(Violation::new(&field.name, "required", "missing"));
+                    }
+                    values.push(String::new());
+                    continue;
+                }
+                Some(Value::String(text)) => text.clone(),
+                Some(Value::Number(number)) if field.field_type == FieldType::Int => {
+                    number.to_string()
+                }
+                Some(other) => {
+                    violations.push(Violation::new(
+                        &field.name,
+                        "type",
+                        format!("expected a string, found {}", other),
+                    ));
+                    values.push(String::new());
+                    continue;
+                }
+            };
+
+            match field.check(&raw) {
+                Ok(value) => values.push(value),
+                Err(violation) => {
+                    violations.push(violation);
+                    values.push(raw);
+                }
+            }
+        }
+
+        for rule in &self.cross_field {
+            if let Some(violation) = rule.check(event) {
+                violations.push(violation);
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(values)
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+impl FieldRule {
+    fn check(&self, raw: &str) -> Result<String, Violation> {
+        let violation = |rule: &str, reason: String| Violation::new(&self.name, rule, reason);
+
+        let typed =
+            parse_typed(self.field_type, raw).map_err(|reason| violation("type", reason))?;
+        if let Typed::Int(value) = typed {
+            let below = self.min.is_some_and(|min| value < min);
+            let above = self.max.is_some_and(|max| value > max);
+            if below || above {
+                let bound = |bound: Option<i64>| bound.map_or("..".to_string(), |b| b.to_string());
+                return Err(violation(
+                    "range",
+                    format!(
+                        "{} outside {}..={}",
+                        value,
+                        bound(self.min),
+                        bound(self.max)
+                    ),
+                ));
+            }
+        }
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(raw) {
+                return Err(violation(
+                    "regex",
+                    format!("{} does not match {}", raw, regex.as_str()),
+                ));
+            }
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.iter().any(|value| value == raw) {
+                return Err(violation(
+                    "enum",
+                    format!("{} not one of {}", raw, allowed.join(", ")),
+                ));
+            }
+        }
+
+        Ok(match typed {
+            Typed::Mac(mac) => mac.to_string(),
+            _ => raw.to_string(),
+        })
+    }
+}
+
+impl CrossFieldRule {
+    fn check(&self, event: &Value) -> Option<Violation> {
+        let left = event.get(&self.left).and_then(scalar_text)?;
+        let right = event.get(&self.right).and_then(scalar_text)?;
+        // A malformed side is already reported by its field rule.
+        let left_value = parse_typed(self.field_type, &left).ok()?;
+        let right_value = parse_typed(self.field_type, &right).ok()?;
+
+        let holds = left_value
+            .partial_cmp(&right_value)
+            .is_some_and(|ordering| self.op.holds(ordering));
+        if holds {
+            return None;
+        }
+        Some(Violation::new(
+            &self.left,
+            "cross_field",
+            format!(
+                "{} {} {} fails: {} vs {}",
+                self.left,
+                self.op.symbol(),
+                self.right,
+                left,
+                right
+            ),
+        ))
+    }
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_typed(field_type: FieldType, raw: &str) -> Result<Typed, String> {
+    match field_type {
+        FieldType::String => Ok(Typed::Text(raw.to_string())),
+        FieldType::Int => raw
+            .trim()
+            .parse()
+            .map(Typed::Int)
+            .map_err(|_| format!("{} is not an integer", raw)),
+        FieldType::Ip => raw
+            .parse()
+            .map(Typed::Ip)
+            .map_err(|_| format!("{} is not an IP address", raw)),
+        FieldType::Mac => raw
+            .parse()
+            .map(Typed::Mac)
+            .map_err(|e| format!("{}: {}", e, raw)),
+        FieldType::Rfc3339 => DateTime::parse_from_rfc3339(raw)
+            .map(Typed::Time)
+            .map_err(|e| format!("{} is not an RFC 3339 timestamp: {}", raw, e)),
+    }
+}
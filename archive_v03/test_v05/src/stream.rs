@@ -0,0 +1,191 @@
+// src/stream.rs
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::TableProvider;
+use datafusion::error::Result;
+use datafusion::execution::context::SessionContext;
+use datafusion::logical_expr::{Expr, TableType};
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::ExecutionPlan;
+use udf::register_udfs;
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Table holding only the most recently appended batch.
+pub const LATEST_TABLE: &str = "mac_table";
+/// Table holding every batch still inside the retention window.
+pub const WINDOW_TABLE: &str = "events";
+
+const DEFAULT_RETENTION: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_MAX_ROWS: usize = 1_000_000;
+
+struct Entry {
+    arrived: Instant,
+    batch: RecordBatch,
+}
+
+/// Batches appended by the processor, shared by the tables registered in a
+/// `ProcessingContext`. Old batches are dropped by age and by total rows.
+pub struct StreamBuffer {
+    schema: SchemaRef,
+    retention: Duration,
+    max_rows: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl StreamBuffer {
+    fn new(schema: SchemaRef, retention: Duration, max_rows: usize) -> Self {
+        StreamBuffer {
+            schema,
+            retention,
+            max_rows,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn append(&self, batch: RecordBatch) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(Entry {
+            arrived: now,
+            batch,
+        });
+
+        let mut rows: usize = entries.iter().map(|entry| entry.batch.num_rows()).sum();
+        // The newest batch always stays, whatever its size.
+        while entries.len() > 1 {
+            let oldest = &entries[0];
+            let expired = now.duration_since(oldest.arrived) > self.retention;
+            if !expired && rows <= self.max_rows {
+                break;
+            }
+            rows -= oldest.batch.num_rows();
+            entries.pop_front();
+        }
+    }
+
+    fn batches(&self, view: StreamView) -> Vec<RecordBatch> {
+        let entries = self.entries.lock().unwrap();
+        match view {
+            StreamView::Latest => entries
+                .back()
+                .map(|entry| entry.batch.clone())
+                .into_iter()
+                .collect(),
+            StreamView::Window => entries.iter().map(|entry| entry.batch.clone()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    pub fn num_rows(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().map(|entry| entry.batch.num_rows()).sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamView {
+    Latest,
+    Window,
+}
+
+/// A table provider reading the batches of a `StreamBuffer` at scan time, so
+/// queries planned against it always see the current contents.
+struct StreamTable {
+    buffer: Arc<StreamBuffer>,
+    view: StreamView,
+}
+
+#[async_trait]
+impl TableProvider for StreamTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.buffer.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let batches = self.buffer.batches(self.view);
+        Ok(Arc::new(MemoryExec::try_new(
+            &[batches],
+            self.schema(),
+            projection.cloned(),
+        )?))
+    }
+}
+
+/// One `SessionContext` for the lifetime of the processor. Each incoming
+/// batch is appended instead of registering a new table: `mac_table` sees
+/// just that batch, `events` sees the retained window for aggregates that
+/// span batches. The network UDFs of `udf` are registered up front.
+pub struct ProcessingContext {
+    ctx: SessionContext,
+    buffer: Arc<StreamBuffer>,
+}
+
+impl ProcessingContext {
+    pub fn new(schema: SchemaRef) -> Self {
+        Self::with_retention(schema, DEFAULT_RETENTION, DEFAULT_MAX_ROWS)
+    }
+
+    /// Keeps batches for `retention` after they arrive, and at most `max_rows`
+    /// rows in total.
+    pub fn with_retention(schema: SchemaRef, retention: Duration, max_rows: usize) -> Self {
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        let buffer = Arc::new(StreamBuffer::new(schema, retention, max_rows));
+        for (name, view) in [
+            (LATEST_TABLE, StreamView::Latest),
+            (WINDOW_TABLE, StreamView::Window),
+        ] {
+            let table = StreamTable {
+                buffer: Arc::clone(&buffer),
+                view,
+            };
+            ctx.register_table(name, Arc::new(table))
+                .expect("a new context has no tables yet");
+        }
+        ProcessingContext { ctx, buffer }
+    }
+
+    pub fn append(&self, batch: RecordBatch) {
+        self.buffer.append(batch);
+    }
+
+    pub async fn sql(&self, query: &str) -> Result<DataFrame> {
+        self.ctx.sql(query).await
+    }
+
+    pub fn session(&self) -> &SessionContext {
+        &self.ctx
+    }
+
+    pub fn buffer(&self) -> &StreamBuffer {
+        &self.buffer
+    }
+}
@@ -0,0 +1,223 @@
+// src/vendors.rs
+use crate::database::{database_path, get_pool, initialize_database};
+use crate::error::AppError;
+
+use rusqlite::{params, Connection};
+use udf::mac::MacAddress;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Prefix lengths of the IEEE registries, longest first so lookups try the
+/// most specific assignment before falling back to the MA-L block.
+pub const PREFIX_BITS: [u8; 3] = [36, 28, 24];
+
+const MA_L_BITS: u8 = 24;
+const ID_BITS_SHIFT: u32 = 40;
+
+const USAGE: &str = "Usage: test_v05 vendors <command>
+
+Commands:
+  import <file.csv>...   load IEEE MA-L, MA-M, MA-S (or IAB) registry CSV files
+  lookup <mac>...        print the registered vendor of each MAC address";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vendor {
+    pub id: i64,
+    pub designation: String,
+    pub org_name: Option<String>,
+    pub prefix_bits: u8,
+}
+
+/// Number of prefix bits assigned by an IEEE registry, as named in the
+/// `Registry` column of its CSV file.
+pub fn registry_prefix_bits(registry: &str) -> Option<u8> {
+    match registry {
+        "MA-L" => Some(24),
+        "MA-M" => Some(28),
+        "MA-S" | "IAB" => Some(36),
+        _ => None,
+    }
+}
+
+/// `mac_vendors.id` of a prefix. MA-L ids are the plain OUI so they match
+/// `MacAddress::oui_id`; longer prefixes carry their length above bit 40.
+pub fn vendor_id(prefix: u64, bits: u8) -> i64 {
+    if bits == MA_L_BITS {
+        prefix as i64
+    } else {
+        ((bits as i64) << ID_BITS_SHIFT) | prefix as i64
+    }
+}
+
+/// `28:6F:B9` for an MA-L block, `70:B3:D5:0/28` or `70:B3:D5:00:1/36` for longer ones.
+pub fn designation(prefix: u64, bits: u8) -> String {
+    let digits = format!("{:0width$X}", prefix, width = bits as usize / 4);
+    let groups: Vec<&str> = digits
+        .as_bytes()
+        .chunks(2)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect();
+    if bits == MA_L_BITS {
+        groups.join(":")
+    } else {
+        format!("{}/{}", groups.join(":"), bits)
+    }
+}
+
+/// Loads one IEEE registry CSV (`Registry,Assignment,Organization Name,...`)
+/// into `mac_vendors`, replacing earlier rows for the same prefixes.
+pub fn import_registry<P: AsRef<Path>>(conn: &mut Connection, path: P) -> Result<usize, AppError> {
+    let path = path.as_ref();
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| {
+                AppError::EventProcessingError(format!(
+                    "{}: missing {} column",
+                    path.display(),
+                    name
+                ))
+            })
+    };
+    let registry_col = column("Registry")?;
+    let assignment_col = column("Assignment")?;
+    let org_col = column("Organization Name")?;
+
+    let tx = conn.transaction()?;
+    let mut imported = 0;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO mac_vendors (id, designation, org_name, prefix_bits) VALUES (?, ?, ?, ?)",
+        )?;
+        for (line, record) in reader.records().enumerate() {
+            let record = record?;
+            let invalid = |msg: &str| {
+                AppError::EventProcessingError(format!("{}:{}: {}", path.display(), line + 2, msg))
+            };
+            let registry = record.get(registry_col).unwrap_or_default().trim();
+            let Some(bits) = registry_prefix_bits(registry) else {
+                // CID assignments are company ids, not MAC prefixes.
+                continue;
+            };
+            let assignment = record.get(assignment_col).unwrap_or_default().trim();
+            if assignment.len() != bits as usize / 4 {
+                return Err(invalid(&format!(
+                    "{} assignment {} is not {} bits",
+                    registry, assignment, bits
+                )));
+            }
+            let prefix = u64::from_str_radix(assignment, 16)
+                .map_err(|_| invalid(&format!("invalid assignment {}", assignment)))?;
+            let org_name = record.get(org_col).unwrap_or_default().trim();
+
+            stmt.execute(params![
+                vendor_id(prefix, bits),
+                designation(prefix, bits),
+                org_name,
+                bits
+            ])?;
+            imported += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(imported)
+}
+
+/// In-memory copy of `mac_vendors` answering longest-prefix lookups.
+#[derive(Debug, Default)]
+pub struct VendorRegistry {
+    prefixes: HashMap<(u8, u64), Vendor>,
+}
+
+impl VendorRegistry {
+    pub fn load(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        let mut stmt =
+            conn.prepare("SELECT id, designation, org_name, prefix_bits FROM mac_vendors")?;
+        let vendors = stmt.query_map([], |row| {
+            Ok(Vendor {
+                id: row.get(0)?,
+                designation: row.get(1)?,
+                org_name: row.get(2)?,
+                prefix_bits: row.get(3)?,
+            })
+        })?;
+
+        let mut prefixes = HashMap::new();
+        for vendor in vendors {
+            let vendor = vendor?;
+            let prefix = vendor.id as u64 & ((1 << ID_BITS_SHIFT) - 1);
+            prefixes.insert((vendor.prefix_bits, prefix), vendor);
+        }
+        Ok(VendorRegistry { prefixes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The most specific registered block containing `mac`. Locally
+    /// administered addresses never match: their OUI is not assigned.
+    pub fn lookup(&self, mac: &MacAddress) -> Option<&Vendor> {
+        if mac.is_locally_administered() {
+            return None;
+        }
+        let value = mac.to_u64();
+        PREFIX_BITS
+            .iter()
+            .find_map(|&bits| self.prefixes.get(&(bits, value >> (48 - bits as u32))))
+    }
+}
+
+/// Entry point for `test_v05 vendors ...`.
+pub fn run(args: &[String]) -> Result<(), AppError> {
+    let usage_error = |msg: &str| AppError::EventProcessingError(format!("{}\n\n{}", msg, USAGE));
+    let command = args.first().ok_or_else(|| usage_error("missing command"))?;
+    if args.len() < 2 {
+        return Err(usage_error("missing arguments"));
+    }
+
+    let pool = get_pool(&database_path())?;
+    initialize_database(&pool)?;
+    let mut conn = pool.get()?;
+
+    match command.as_str() {
+        "import" => {
+            for path in &args[1..] {
+                let imported = import_registry(&mut conn, path)?;
+                println!("Imported {} vendor prefixes from {}", imported, path);
+            }
+            Ok(())
+        }
+        "lookup" => {
+            let registry = VendorRegistry::load(&conn)?;
+            for input in &args[1..] {
+                let mac: MacAddress = match input.parse() {
+                    Ok(mac) => mac,
+                    Err(e) => {
+                        println!("{}: {}", input, e);
+                        continue;
+                    }
+                };
+                match registry.lookup(&mac) {
+                    Some(vendor) => println!(
+                        "{} {} {}",
+                        mac,
+                        vendor.designation,
+                        vendor.org_name.as_deref().unwrap_or("-")
+                    ),
+                    None => println!("{} unknown vendor", mac),
+                }
+            }
+            Ok(())
+        }
+        _ => Err(usage_error(&format!("unknown command: {}", command))),
+    }
+}
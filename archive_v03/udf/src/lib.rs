@@ -0,0 +1,373 @@
+// src/lib.rs
+pub mod mac;
+pub mod write;
+
+use mac::MacAddress;
+
+use datafusion::arrow::array::{ArrayRef, BooleanArray, Int64Array, StringArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::cast::{as_int64_array, as_string_array};
+use datafusion::error::Result;
+use datafusion::execution::context::SessionContext;
+use datafusion::logical_expr::{
+    ColumnarValue, ScalarFunctionArgs, ScalarUDF, ScalarUDFImpl, Signature, Volatility,
+};
+
+use std::any::Any;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+/// Computes a function over arguments already cast to its input types.
+type Kernel = fn(&[ArrayRef]) -> Result<ArrayRef>;
+
+/// A scalar function over network and telecom identifiers. Arguments of any
+/// type are cast to `inputs` first, so the functions work on the all-Utf8
+/// event batches as well as on typed Parquet columns. Values that do not
+/// parse give NULL rather than failing the query.
+#[derive(Debug)]
+struct NetworkUdf {
+    name: &'static str,
+    inputs: Vec<DataType>,
+    output: DataType,
+    signature: Signature,
+    kernel: Kernel,
+}
+
+impl NetworkUdf {
+    fn new(name: &'static str, inputs: Vec<DataType>, output: DataType, kernel: Kernel) -> Self {
+        NetworkUdf {
+            name,
+            signature: Signature::any(inputs.len(), Volatility::Immutable),
+            inputs,
+            output,
+            kernel,
+        }
+    }
+}
+
+impl ScalarUDFImpl for NetworkUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(self.output.clone())
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> Result<ColumnarValue> {
+        let arrays = args
+            .args
+            .iter()
+            .zip(&self.inputs)
+            .map(|(arg, data_type)| Ok(cast(&arg.to_array(args.number_rows)?, data_type)?))
+            .collect::<Result<Vec<_>>>()?;
+        (self.kernel)(&arrays).map(ColumnarValue::Array)
+    }
+}
+
+/// Every function of the library:
+///
+/// | function                   | returns                                 |
+/// |----------------------------|-----------------------------------------|
+/// | `ip_to_int(ip)`            | IPv4 address as an integer              |
+/// | `int_to_ip(n)`             | integer as a dotted IPv4 address        |
+/// | `cidr_contains(ip, cidr)`  | whether `ip` is inside `10.0.0.0/8`     |
+/// | `mac_oui(mac)`             | OUI as `28:6F:B9`                       |
+/// | `mac_normalize(mac)`       | MAC as `28:6F:B9:12:34:56`              |
+/// | `msisdn_country(msisdn)`   | ISO country code of the calling code    |
+/// | `imsi_mcc_mnc(imsi)`       | home network as `603-11`                |
+/// | `luhn_valid(imei)`         | whether the Luhn check digit is right   |
+/// | `port_service(port, prot)` | IANA service name, e.g. `https`         |
+pub fn udfs() -> Vec<ScalarUDF> {
+    use DataType::{Boolean, Int64, Utf8};
+
+    vec![
+        NetworkUdf::new("ip_to_int", vec![Utf8], Int64, |args| {
+            let ips = as_string_array(&args[0])?;
+            let ints: Int64Array = ips
+                .iter()
+                .map(|ip| {
+                    let ip: Ipv4Addr = ip?.trim().parse().ok()?;
+                    Some(i64::from(u32::from(ip)))
+                })
+                .collect();
+            Ok(Arc::new(ints))
+        }),
+        NetworkUdf::new("int_to_ip", vec![Int64], Utf8, |args| {
+            let ints = as_int64_array(&args[0])?;
+            let ips: StringArray = ints
+                .iter()
+                .map(|n| {
+                    n.and_then(|n| u32::try_from(n).ok())
+                        .map(|n| Ipv4Addr::from(n).to_string())
+                })
+                .collect();
+            Ok(Arc::new(ips))
+        }),
+        NetworkUdf::new("cidr_contains", vec![Utf8, Utf8], Boolean, |args| {
+            let ips = as_string_array(&args[0])?;
+            let cidrs = as_string_array(&args[1])?;
+            let contained: BooleanArray = ips
+                .iter()
+                .zip(cidrs.iter())
+                .map(|(ip, cidr)| cidr_contains(ip?, cidr?))
+                .collect();
+            Ok(Arc::new(contained))
+        }),
+        NetworkUdf::new("mac_oui", vec![Utf8], Utf8, |args| {
+            map_strings(&args[0], |mac| {
+                mac.parse::<MacAddress>().ok().map(|mac| mac.oui_string())
+            })
+        }),
+        NetworkUdf::new("mac_normalize", vec![Utf8], Utf8, |args| {
+            map_strings(&args[0], |mac| {
+                mac.parse::<MacAddress>().ok().map(|mac| mac.to_string())
+            })
+        }),
+        NetworkUdf::new("msisdn_country", vec![Utf8], Utf8, |args| {
+            map_strings(&args[0], |msisdn| {
+                msisdn_country(msisdn).map(str::to_string)
+            })
+        }),
+        NetworkUdf::new("imsi_mcc_mnc", vec![Utf8], Utf8, |args| {
+            map_strings(&args[0], |imsi| {
+                imsi_mcc_mnc(imsi).map(|(mcc, mnc)| format!("{}-{}", mcc, mnc))
+            })
+        }),
+        NetworkUdf::new("luhn_valid", vec![Utf8], Boolean, |args| {
+            let numbers = as_string_array(&args[0])?;
+            let valid: BooleanArray = numbers
+                .iter()
+                .map(|number| number.map(luhn_valid))
+                .collect();
+            Ok(Arc::new(valid))
+        }),
+        NetworkUdf::new("port_service", vec![Int64, Utf8], Utf8, |args| {
+            let ports = as_int64_array(&args[0])?;
+            let protocols = as_string_array(&args[1])?;
+            let services: StringArray = ports
+                .iter()
+                .zip(protocols.iter())
+                .map(|(port, protocol)| port_service(port?, protocol?))
+                .collect();
+            Ok(Arc::new(services))
+        }),
+    ]
+    .into_iter()
+    .map(ScalarUDF::new_from_impl)
+    .collect()
+}
+
+pub fn register_udfs(ctx: &SessionContext) {
+    for udf in udfs() {
+        ctx.register_udf(udf);
+    }
+}
+
+/// Applies `f` to every non-null string, NULL where it returns `None`.
+fn map_strings(array: &ArrayRef, f: impl Fn(&str) -> Option<String>) -> Result<ArrayRef> {
+    let strings = as_string_array(array)?;
+    let mapped: StringArray = strings.iter().map(|value| value.and_then(&f)).collect();
+    Ok(Arc::new(mapped))
+}
+
+/// `cidr` may be a bare address, meaning a single host. A malformed address
+/// or network, or one of the other IP version, gives `None`.
+pub fn cidr_contains(ip: &str, cidr: &str) -> Option<bool> {
+    let ip: IpAddr = ip.trim().parse().ok()?;
+    let (network, prefix): (IpAddr, Option<u32>) = match cidr.trim().split_once('/') {
+        Some((network, prefix)) => (network.parse().ok()?, Some(prefix.parse::<u32>().ok()?)),
+        None => (cidr.trim().parse().ok()?, None),
+    };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let prefix = prefix.unwrap_or(32);
+            if prefix > 32 {
+                return None;
+            }
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            Some(u32::from(ip) & mask == u32::from(network) & mask)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let prefix = prefix.unwrap_or(128);
+            if prefix > 128 {
+                return None;
+            }
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            Some(u128::from(ip) & mask == u128::from(network) & mask)
+        }
+        _ => None,
+    }
+}
+
+/// ITU country calling codes and their ISO 3166 country, covering the
+/// ranges of the CDR generator's `config.yaml` and our roaming partners.
+/// Calling codes are prefix free, so at most one entry matches a number.
+const CALLING_CODES: [(&str, &str); 34] = [
+    ("1", "US"),
+    ("7", "RU"),
+    ("20", "EG"),
+    ("27", "ZA"),
+    ("30", "GR"),
+    ("31", "NL"),
+    ("32", "BE"),
+    ("33", "FR"),
+    ("34", "ES"),
+    ("39", "IT"),
+    ("41", "CH"),
+    ("44", "GB"),
+    ("49", "DE"),
+    ("81", "JP"),
+    ("86", "CN"),
+    ("90", "TR"),
+    ("91", "IN"),
+    ("211", "SS"),
+    ("212", "MA"),
+    ("213", "DZ"),
+    ("216", "TN"),
+    ("218", "LY"),
+    ("221", "SN"),
+    ("222", "MR"),
+    ("225", "CI"),
+    ("351", "PT"),
+    ("352", "LU"),
+    ("961", "LB"),
+    ("962", "JO"),
+    ("965", "KW"),
+    ("966", "SA"),
+    ("971", "AE"),
+    ("973", "BH"),
+    ("974", "QA"),
+];
+
+/// Country of an international number written as `+21650123456` or
+/// `0021650123456`, as produced by the CDR generator.
+pub fn msisdn_country(msisdn: &str) -> Option<&'static str> {
+    let msisdn = msisdn.trim();
+    let digits = msisdn
+        .strip_prefix('+')
+        .or_else(|| msisdn.strip_prefix("00"))?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    CALLING_CODES
+        .iter()
+        .find(|(code, _)| digits.starts_with(code))
+        .map(|(_, country)| *country)
+}
+
+/// MCCs whose networks use three digit MNCs (North America, the Caribbean
+/// and parts of Latin America). Everywhere else the MNC has two digits.
+const THREE_DIGIT_MNC: [&str; 19] = [
+    "302", "310", "311", "312", "313", "314", "315", "316", "334", "338", "342", "344", "346",
+    "348", "354", "356", "358", "722", "732",
+];
+
+/// Splits an IMSI into its mobile country and network codes.
+pub fn imsi_mcc_mnc(imsi: &str) -> Option<(&str, &str)> {
+    let imsi = imsi.trim();
+    if !(6..=15).contains(&imsi.len()) || !imsi.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mcc = &imsi[..3];
+    let mnc_len = if THREE_DIGIT_MNC.contains(&mcc) { 3 } else { 2 };
+    Some((mcc, &imsi[3..3 + mnc_len]))
+}
+
+/// The digit that makes `number` pass the Luhn check, as appended to the
+/// 14 digits of a generated IMEI.
+pub fn luhn_checksum(number: &str) -> Option<u8> {
+    let mut sum = 0u32;
+    for (i, c) in number.chars().rev().enumerate() {
+        let d = c.to_digit(10)?;
+        sum += if i % 2 == 0 {
+            let doubled = d * 2;
+            if doubled > 9 {
+                doubled - 9
+            } else {
+                doubled
+            }
+        } else {
+            d
+        };
+    }
+    Some(((10 - sum % 10) % 10) as u8)
+}
+
+/// Whether the last digit of `number` is its Luhn check digit.
+pub fn luhn_valid(number: &str) -> bool {
+    let number = number.trim();
+    match number.char_indices().last() {
+        Some((i, last)) if i > 0 => {
+            let (payload, check) = (&number[..i], last.to_digit(10));
+            check.is_some() && luhn_checksum(payload).map(u32::from) == check
+        }
+        _ => false,
+    }
+}
+
+/// IANA service names of the ports seen in our traffic.
+const SERVICES: [(i64, &str, &str); 44] = [
+    (20, "tcp", "ftp-data"),
+    (21, "tcp", "ftp"),
+    (22, "tcp", "ssh"),
+    (23, "tcp", "telnet"),
+    (25, "tcp", "smtp"),
+    (53, "tcp", "domain"),
+    (53, "udp", "domain"),
+    (67, "udp", "bootps"),
+    (68, "udp", "bootpc"),
+    (69, "udp", "tftp"),
+    (80, "tcp", "http"),
+    (110, "tcp", "pop3"),
+    (123, "udp", "ntp"),
+    (143, "tcp", "imap"),
+    (161, "udp", "snmp"),
+    (162, "udp", "snmptrap"),
+    (179, "tcp", "bgp"),
+    (389, "tcp", "ldap"),
+    (443, "tcp", "https"),
+    (443, "udp", "https"),
+    (445, "tcp", "microsoft-ds"),
+    (500, "udp", "isakmp"),
+    (514, "udp", "syslog"),
+    (587, "tcp", "submission"),
+    (636, "tcp", "ldaps"),
+    (853, "tcp", "domain-s"),
+    (993, "tcp", "imaps"),
+    (995, "tcp", "pop3s"),
+    (1194, "udp", "openvpn"),
+    (1812, "udp", "radius"),
+    (1813, "udp", "radius-acct"),
+    (2123, "udp", "gtp-control"),
+    (2152, "udp", "gtp-user"),
+    (3306, "tcp", "mysql"),
+    (3389, "tcp", "ms-wbt-server"),
+    (3868, "tcp", "diameter"),
+    (3868, "sctp", "diameter"),
+    (4500, "udp", "ipsec-nat-t"),
+    (5060, "tcp", "sip"),
+    (5060, "udp", "sip"),
+    (5061, "tcp", "sips"),
+    (5432, "tcp", "postgresql"),
+    (6514, "tcp", "syslog-tls"),
+    (8080, "tcp", "http-alt"),
+];
+
+/// Service name of `port` over `protocol` (`tcp`, `udp` or `sctp`, in any case).
+pub fn port_service(port: i64, protocol: &str) -> Option<&'static str> {
+    let protocol = protocol.trim();
+    SERVICES
+        .iter()
+        .find(|(p, proto, _)| *p == port && proto.eq_ignore_ascii_case(protocol))
+        .map(|(_, _, service)| *service)
+}
@@ -0,0 +1,156 @@
+// src/mac.rs
+use std::fmt;
+use std::str::FromStr;
+
+/// A 48-bit MAC address, always rendered as `28:6F:B9:12:34:56`.
+///
+/// Accepts colon, dash or dot separated octets, Cisco `286f.b912.3456`
+/// notation and bare hex, in either case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddress([u8; 6]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacParseError {
+    Empty,
+    InvalidCharacter(char),
+    MixedSeparators,
+    InvalidGrouping(String),
+    WrongLength(usize),
+}
+
+impl std::error::Error for MacParseError {}
+
+impl fmt::Display for MacParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacParseError::Empty => write!(f, "empty MAC address"),
+            MacParseError::InvalidCharacter(c) => write!(f, "invalid character {:?}", c),
+            MacParseError::MixedSeparators => write!(f, "mixed separators"),
+            MacParseError::InvalidGrouping(groups) => {
+                write!(f, "invalid digit grouping {}", groups)
+            }
+            MacParseError::WrongLength(digits) => {
+                write!(f, "expected 12 hex digits, found {}", digits)
+            }
+        }
+    }
+}
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+
+    pub fn new(octets: [u8; 6]) -> Self {
+        MacAddress(octets)
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Organizationally unique identifier: the first three octets.
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    /// Network interface controller specific part: the last three octets.
+    pub fn nic(&self) -> [u8; 3] {
+        [self.0[3], self.0[4], self.0[5]]
+    }
+
+    /// The OUI as an integer, as used for `mac_vendors.id`.
+    pub fn oui_id(&self) -> u32 {
+        u32::from_be_bytes([0, self.0[0], self.0[1], self.0[2]])
+    }
+
+    /// The OUI as `28:6F:B9`, as used for `mac_vendors.designation`.
+    pub fn oui_string(&self) -> String {
+        format!("{:02X}:{:02X}:{:02X}", self.0[0], self.0[1], self.0[2])
+    }
+
+    /// The whole address as an integer, as used for `mac_addresses.id`.
+    pub fn to_u64(&self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes[2..].copy_from_slice(&self.0);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// U/L bit set: the address was assigned locally, the OUI names no vendor.
+    pub fn is_locally_administered(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    pub fn is_universal(&self) -> bool {
+        !self.is_locally_administered()
+    }
+
+    /// I/G bit set: the address names a group rather than one interface.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = MacParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(MacParseError::Empty);
+        }
+
+        let mut separator = None;
+        for c in input.chars() {
+            if c.is_ascii_hexdigit() {
+                continue;
+            }
+            if !matches!(c, ':' | '-' | '.') {
+                return Err(MacParseError::InvalidCharacter(c));
+            }
+            match separator {
+                None => separator = Some(c),
+                Some(seen) if seen != c => return Err(MacParseError::MixedSeparators),
+                Some(_) => {}
+            }
+        }
+
+        let digits: String = input.chars().filter(char::is_ascii_hexdigit).collect();
+        if digits.len() != 12 {
+            return Err(MacParseError::WrongLength(digits.len()));
+        }
+
+        if let Some(separator) = separator {
+            let groups: Vec<usize> = input.split(separator).map(str::len).collect();
+            let valid = groups == [2; 6] || (separator == '.' && groups == [4; 3]);
+            if !valid {
+                let groups: Vec<String> = groups.iter().map(usize::to_string).collect();
+                return Err(MacParseError::InvalidGrouping(groups.join("-")));
+            }
+        }
+
+        let mut octets = [0; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| MacParseError::InvalidCharacter(digits.as_bytes()[i * 2] as char))?;
+        }
+        Ok(MacAddress(octets))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
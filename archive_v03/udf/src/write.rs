@@ -0,0 +1,27 @@
+// src/write.rs
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::cast::as_uint64_array;
+use datafusion::error::Result;
+
+use std::path::Path;
+
+/// `dir` as a DataFusion location. The trailing slash makes DataFusion treat
+/// it as a directory: reads list the files inside and writes add a new file
+/// instead of replacing the previous one.
+pub fn dir_location(dir: &Path) -> String {
+    format!("{}/", dir.to_string_lossy().trim_end_matches('/'))
+}
+
+/// Sums the `count` column DataFusion returns from a write.
+pub fn written_rows(batches: &[RecordBatch]) -> Result<u64> {
+    let mut total = 0;
+    for batch in batches {
+        if batch.num_columns() > 0 {
+            total += as_uint64_array(batch.column(0))?
+                .iter()
+                .flatten()
+                .sum::<u64>();
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,620 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Db, Transactional, Tree};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+const VERSION_LEN: usize = 4;
+const STRING_ESCAPE: u8 = 0xFF;
+const STRING_END: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    Sled(sled::Error),
+    Serde(serde_json::Error),
+    InvalidKey(String),
+    Migration(String),
+    UnknownIndex(String),
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepositoryError::Sled(e) => write!(f, "Sled error: {}", e),
+            RepositoryError::Serde(e) => write!(f, "Serde JSON error: {}", e),
+            RepositoryError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            RepositoryError::Migration(msg) => write!(f, "Migration error: {}", msg),
+            RepositoryError::UnknownIndex(name) => write!(f, "Unknown index: {}", name),
+        }
+    }
+}
+
+impl From<sled::Error> for RepositoryError {
+    fn from(e: sled::Error) -> Self {
+        RepositoryError::Sled(e)
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(e: serde_json::Error) -> Self {
+        RepositoryError::Serde(e)
+    }
+}
+
+impl From<TransactionError<RepositoryError>> for RepositoryError {
+    fn from(e: TransactionError<RepositoryError>) -> Self {
+        match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => RepositoryError::Sled(e),
+        }
+    }
+}
+
+/// A key whose byte encoding sorts like the key itself.
+///
+/// Encodings are self-delimiting, so tuples of keys can be concatenated and
+/// scanned by their leading components.
+pub trait Key: Sized {
+    fn write_key(&self, out: &mut Vec<u8>);
+
+    /// Decodes one key from the front of `bytes`, returning the unread rest.
+    fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError>;
+
+    fn to_key(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_key(&mut out);
+        out
+    }
+
+    fn from_key(bytes: &[u8]) -> Result<Self, RepositoryError> {
+        let (key, rest) = Self::read_key(bytes)?;
+        if !rest.is_empty() {
+            return Err(RepositoryError::InvalidKey(format!(
+                "{} trailing bytes",
+                rest.len()
+            )));
+        }
+        Ok(key)
+    }
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        impl Key for $t {
+            fn write_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+
+            fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError> {
+                const LEN: usize = std::mem::size_of::<$t>();
+                if bytes.len() < LEN {
+                    return Err(RepositoryError::InvalidKey(format!(
+                        "expected {} bytes for {}",
+                        LEN,
+                        stringify!($t)
+                    )));
+                }
+                let (head, rest) = bytes.split_at(LEN);
+                Ok((<$t>::from_be_bytes(head.try_into().unwrap()), rest))
+            }
+        }
+    )*};
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => {$(
+        // Flipping the sign bit makes negative numbers sort before positive ones.
+        impl Key for $t {
+            fn write_key(&self, out: &mut Vec<u8>) {
+                ((*self as $u) ^ (1 << (<$u>::BITS - 1))).write_key(out);
+            }
+
+            fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError> {
+                let (raw, rest) = <$u>::read_key(bytes)?;
+                Ok(((raw ^ (1 << (<$u>::BITS - 1))) as $t, rest))
+            }
+        }
+    )*};
+}
+
+signed_key!(i32 => u32, i64 => u64);
+
+// Zero bytes are escaped and the string is terminated by `0x00 0x01`, which
+// keeps byte order equal to string order and lets a string be a key prefix.
+impl Key for String {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        for byte in self.bytes() {
+            out.push(byte);
+            if byte == 0 {
+                out.push(STRING_ESCAPE);
+            }
+        }
+        out.extend_from_slice(&[0, STRING_END]);
+    }
+
+    fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError> {
+        let mut value = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == 0 {
+                match bytes.get(i + 1) {
+                    Some(&STRING_ESCAPE) => value.push(0),
+                    Some(&STRING_END) => {
+                        let value = String::from_utf8(value)
+                            .map_err(|e| RepositoryError::InvalidKey(e.to_string()))?;
+                        return Ok((value, &bytes[i + 2..]));
+                    }
+                    _ => break,
+                }
+                i += 2;
+            } else {
+                value.push(bytes[i]);
+                i += 1;
+            }
+        }
+        Err(RepositoryError::InvalidKey(
+            "unterminated string".to_string(),
+        ))
+    }
+}
+
+impl<A: Key, B: Key> Key for (A, B) {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        self.0.write_key(out);
+        self.1.write_key(out);
+    }
+
+    fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError> {
+        let (a, rest) = A::read_key(bytes)?;
+        let (b, rest) = B::read_key(rest)?;
+        Ok(((a, b), rest))
+    }
+}
+
+impl<A: Key, B: Key, C: Key> Key for (A, B, C) {
+    fn write_key(&self, out: &mut Vec<u8>) {
+        self.0.write_key(out);
+        self.1.write_key(out);
+        self.2.write_key(out);
+    }
+
+    fn read_key(bytes: &[u8]) -> Result<(Self, &[u8]), RepositoryError> {
+        let (a, rest) = A::read_key(bytes)?;
+        let (b, rest) = B::read_key(rest)?;
+        let (c, rest) = C::read_key(rest)?;
+        Ok(((a, b, c), rest))
+    }
+}
+
+type Extractor<V> = Box<dyn Fn(&V) -> Vec<u8> + Send + Sync>;
+type Migration = Box<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
+struct Index<V> {
+    name: String,
+    tree: Tree,
+    extract: Extractor<V>,
+}
+
+/// Typed values stored as versioned JSON in one sled tree.
+///
+/// Secondary index trees are named `<table>.idx.<index>` and updated in the
+/// same transaction as the table.
+pub struct Repository<K, V> {
+    db: Db,
+    name: String,
+    tree: Tree,
+    indexes: Vec<Index<V>>,
+    version: u32,
+    migrations: BTreeMap<u32, Migration>,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key, V: Serialize + DeserializeOwned> Repository<K, V> {
+    pub fn open(db: &Db, name: &str) -> Result<Self, RepositoryError> {
+        Ok(Repository {
+            db: db.clone(),
+            name: name.to_string(),
+            tree: db.open_tree(name)?,
+            indexes: Vec::new(),
+            version: 1,
+            migrations: BTreeMap::new(),
+            _key: PhantomData,
+        })
+    }
+
+    /// Adds an index on the key returned by `extract`; call `reindex` if the table already has data.
+    pub fn with_index<I, F>(mut self, name: &str, extract: F) -> Result<Self, RepositoryError>
+    where
+        I: Key,
+        F: Fn(&V) -> I + Send + Sync + 'static,
+    {
+        let tree = self.db.open_tree(format!("{}.idx.{}", self.name, name))?;
+        self.indexes.push(Index {
+            name: name.to_string(),
+            tree,
+            extract: Box::new(move |value| extract(value).to_key()),
+        });
+        Ok(self)
+    }
+
+    /// Version written with new values. Older values are upgraded on read.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Upgrades a value stored as `from_version` to `from_version + 1`.
+    pub fn with_migration<F>(mut self, from_version: u32, migrate: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.migrations.insert(from_version, Box::new(migrate));
+        self
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, RepositoryError> {
+        match self.tree.get(key.to_key())? {
+            Some(bytes) => Ok(Some(self.decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> Result<(), RepositoryError> {
+        let key = key.to_key();
+        let encoded = self.encode(value)?;
+        let entries: Vec<Vec<u8>> = self
+            .indexes
+            .iter()
+            .map(|index| index_entry(&(index.extract)(value), &key))
+            .collect();
+
+        self.transaction(|tree, index_trees| {
+            if let Some(old) = tree.get(&key)? {
+                let old = self
+                    .decode(&old)
+                    .map_err(ConflictableTransactionError::Abort)?;
+                for (index, index_tree) in self.indexes.iter().zip(index_trees) {
+                    index_tree.remove(index_entry(&(index.extract)(&old), &key))?;
+                }
+            }
+            tree.insert(key.as_slice(), encoded.as_slice())?;
+            for (entry, index_tree) in entries.iter().zip(index_trees) {
+                index_tree.insert(entry.as_slice(), &[])?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn delete(&self, key: &K) -> Result<Option<V>, RepositoryError> {
+        let key = key.to_key();
+        self.transaction(|tree, index_trees| match tree.remove(key.as_slice())? {
+            Some(old) => {
+                let old = self
+                    .decode(&old)
+                    .map_err(ConflictableTransactionError::Abort)?;
+                for (index, index_tree) in self.indexes.iter().zip(index_trees) {
+                    index_tree.remove(index_entry(&(index.extract)(&old), &key))?;
+                }
+                Ok(Some(old))
+            }
+            None => Ok(None),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V), RepositoryError>> + '_ {
+        self.tree.iter().map(move |item| self.decode_entry(item))
+    }
+
+    /// Entries whose key starts with the leading key component(s) `prefix`.
+    pub fn scan_prefix<P: Key>(
+        &self,
+        prefix: &P,
+    ) -> impl Iterator<Item = Result<(K, V), RepositoryError>> + '_ {
+        self.tree
+            .scan_prefix(prefix.to_key())
+            .map(move |item| self.decode_entry(item))
+    }
+
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = Result<(K, V), RepositoryError>> + '_ {
+        let bounds = (
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+        );
+        self.tree
+            .range::<Vec<u8>, _>(bounds)
+            .map(move |item| self.decode_entry(item))
+    }
+
+    /// The entry with the greatest key.
+    pub fn last(&self) -> Result<Option<(K, V)>, RepositoryError> {
+        self.tree
+            .last()
+            .transpose()
+            .map(|item| self.decode_entry(item))
+            .transpose()
+    }
+
+    /// Values whose index `name` equals `value`, read without scanning the table.
+    pub fn find_by<I: Key>(&self, name: &str, value: &I) -> Result<Vec<(K, V)>, RepositoryError> {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.name == name)
+            .ok_or_else(|| RepositoryError::UnknownIndex(name.to_string()))?;
+
+        let prefix = value.to_key();
+        let mut results = Vec::new();
+        for entry in index.tree.scan_prefix(&prefix).keys() {
+            let entry = entry?;
+            let key = K::from_key(&entry[prefix.len()..])?;
+            if let Some(value) = self.get(&key)? {
+                results.push((key, value));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Rebuilds every index from the table.
+    pub fn reindex(&self) -> Result<(), RepositoryError> {
+        for index in &self.indexes {
+            index.tree.clear()?;
+            for item in self.tree.iter() {
+                let (key, bytes) = item?;
+                let value = self.decode(&bytes)?;
+                index
+                    .tree
+                    .insert(index_entry(&(index.extract)(&value), &key), &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites values stored with an older version, returning how many were upgraded.
+    pub fn migrate_all(&self) -> Result<usize, RepositoryError> {
+        let mut migrated = 0;
+        for item in self.tree.iter() {
+            let (key, bytes) = item?;
+            if stored_version(&bytes)? < self.version {
+                let value = self.decode(&bytes)?;
+                self.put(&K::from_key(&key)?, &value)?;
+                migrated += 1;
+            }
+        }
+        Ok(migrated)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        F: Fn(
+            &sled::transaction::TransactionalTree,
+            &[sled::transaction::TransactionalTree],
+        ) -> Result<T, ConflictableTransactionError<RepositoryError>>,
+    {
+        let mut trees = vec![self.tree.clone()];
+        trees.extend(self.indexes.iter().map(|index| index.tree.clone()));
+        Ok(trees[..].transaction(|trees| f(&trees[0], &trees[1..]))?)
+    }
+
+    fn encode(&self, value: &V) -> Result<Vec<u8>, RepositoryError> {
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        serde_json::to_writer(&mut bytes, value)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<V, RepositoryError> {
+        let mut version = stored_version(bytes)?;
+        if version > self.version {
+            return Err(RepositoryError::Migration(format!(
+                "{} holds version {} but this build reads up to {}",
+                self.name, version, self.version
+            )));
+        }
+        let mut value: Value = serde_json::from_slice(&bytes[VERSION_LEN..])?;
+        while version < self.version {
+            let migrate = self.migrations.get(&version).ok_or_else(|| {
+                RepositoryError::Migration(format!(
+                    "{} has no migration from version {}",
+                    self.name, version
+                ))
+            })?;
+            value = migrate(value).map_err(RepositoryError::Migration)?;
+            version += 1;
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    fn decode_entry(
+        &self,
+        item: sled::Result<(sled::IVec, sled::IVec)>,
+    ) -> Result<(K, V), RepositoryError> {
+        let (key, bytes) = item?;
+        Ok((K::from_key(&key)?, self.decode(&bytes)?))
+    }
+}
+
+fn index_entry(index_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut entry = index_key.to_vec();
+    entry.extend_from_slice(key);
+    entry
+}
+
+fn stored_version(bytes: &[u8]) -> Result<u32, RepositoryError> {
+    bytes
+        .get(..VERSION_LEN)
+        .map(|head| u32::from_be_bytes(head.try_into().unwrap()))
+        .ok_or_else(|| RepositoryError::Migration("value has no version header".to_string()))
+}
+
+fn encode_bound<K: Key>(bound: Bound<&K>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_key()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_key()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    fn temporary_db() -> Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn assert_sorted_like_keys<K: Key + Ord + fmt::Debug>(mut keys: Vec<K>) {
+        keys.sort();
+        for pair in keys.windows(2) {
+            assert!(
+                pair[0].to_key() < pair[1].to_key(),
+                "{:?} does not encode below {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for value in [0u64, 1, 255, 256, u64::MAX] {
+            assert_eq!(u64::from_key(&value.to_key()).unwrap(), value);
+        }
+        for value in [i64::MIN, -1, 0, 1, i64::MAX] {
+            assert_eq!(i64::from_key(&value.to_key()).unwrap(), value);
+        }
+        for value in ["", "a", "a\0", "\0", "a\0b", "\0\u{1}", "\u{ff}\0\0"] {
+            let value = value.to_string();
+            assert_eq!(String::from_key(&value.to_key()).unwrap(), value);
+        }
+        let composite = ("a\0".to_string(), -7i32, 42u16);
+        assert_eq!(
+            <(String, i32, u16)>::from_key(&composite.to_key()).unwrap(),
+            composite
+        );
+    }
+
+    #[test]
+    fn truncated_and_trailing_keys_are_rejected() {
+        assert!(u32::from_key(&[0, 1]).is_err());
+        assert!(String::from_key(b"abc").is_err());
+        let mut bytes = 7u8.to_key();
+        bytes.push(0);
+        assert!(u8::from_key(&bytes).is_err());
+    }
+
+    #[test]
+    fn byte_order_matches_key_order() {
+        assert_sorted_like_keys(vec![0u32, 1, 255, 256, 65_536, u32::MAX]);
+        assert_sorted_like_keys(vec![i32::MIN, -256, -1, 0, 1, 256, i32::MAX]);
+        assert_sorted_like_keys(vec![i64::MIN, -1, 0, i64::MAX]);
+        assert_sorted_like_keys(
+            [
+                "", "\0", "\0\0", "\0\u{1}", "a", "a\0", "a\0b", "a\u{1}", "ab", "b",
+            ]
+            .iter()
+            .map(|value| value.to_string())
+            .collect(),
+        );
+        assert_sorted_like_keys(vec![
+            ("a".to_string(), 2u32),
+            ("a".to_string(), 10),
+            ("a\0".to_string(), 0),
+            ("ab".to_string(), 0),
+            ("b".to_string(), 1),
+        ]);
+        assert_sorted_like_keys(vec![(-1i64, "z".to_string()), (0, "a".to_string())]);
+    }
+
+    #[test]
+    fn string_prefix_scan_matches_only_that_component() {
+        let db = temporary_db();
+        let repository: Repository<(String, u32), u32> = Repository::open(&db, "prefix").unwrap();
+        for (name, n) in [("a", 1), ("a", 2), ("ab", 3), ("a\0", 4)] {
+            repository.put(&(name.to_string(), n), &n).unwrap();
+        }
+        let values: Vec<u32> = repository
+            .scan_prefix(&"a".to_string())
+            .map(|item| item.unwrap().1)
+            .collect();
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Device {
+        name: String,
+        vendor: String,
+    }
+
+    #[test]
+    fn values_are_migrated_on_read_and_by_migrate_all() {
+        let db = temporary_db();
+        let tree = db.open_tree("devices").unwrap();
+        for (id, name) in [(1u32, "router"), (2, "switch")] {
+            let mut bytes = 1u32.to_be_bytes().to_vec();
+            serde_json::to_writer(&mut bytes, &json!({ "name": name })).unwrap();
+            tree.insert(id.to_key(), bytes).unwrap();
+        }
+
+        let devices: Repository<u32, Device> = Repository::open(&db, "devices")
+            .unwrap()
+            .with_version(2)
+            .with_migration(1, |mut value| {
+                value["vendor"] = json!("unknown");
+                Ok(value)
+            });
+
+        let expected = Device {
+            name: "router".to_string(),
+            vendor: "unknown".to_string(),
+        };
+        assert_eq!(devices.get(&1).unwrap(), Some(expected));
+        assert_eq!(
+            stored_version(&tree.get(1u32.to_key()).unwrap().unwrap()).unwrap(),
+            1
+        );
+
+        assert_eq!(devices.migrate_all().unwrap(), 2);
+        for item in tree.iter() {
+            assert_eq!(stored_version(&item.unwrap().1).unwrap(), 2);
+        }
+        assert_eq!(devices.migrate_all().unwrap(), 0);
+        assert_eq!(devices.get(&2).unwrap().unwrap().vendor, "unknown");
+    }
+
+    #[test]
+    fn missing_migration_and_newer_versions_fail() {
+        let db = temporary_db();
+        let v1: Repository<u32, Value> = Repository::open(&db, "values").unwrap();
+        v1.put(&1, &json!({})).unwrap();
+
+        let v3: Repository<u32, Value> = Repository::open(&db, "values").unwrap().with_version(3);
+        assert!(matches!(v3.get(&1), Err(RepositoryError::Migration(_))));
+
+        let v2: Repository<u32, Value> = Repository::open(&db, "values")
+            .unwrap()
+            .with_version(2)
+            .with_migration(1, Ok);
+        v2.migrate_all().unwrap();
+        assert!(matches!(v1.get(&1), Err(RepositoryError::Migration(_))));
+    }
+}
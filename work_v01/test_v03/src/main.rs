@@ -1,18 +1,15 @@
-use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use repository::{Repository, RepositoryError};
+use serde::de::DeserializeOwned;
 use serde::{Serialize, Deserialize};
-use serde_json;
-use sled::{Db, IVec, Tree, Error};
+use sled::{Db, Error};
 use std::error::Error as StdError;
 use std::fmt;
 
 // Custom error type
 #[derive(Debug)]
-enum CacheError {
-    SledError(Error),
-    SerializationError(serde_json::Error),
-    DeserializationError(serde_json::Error),
-    CacheError(String),
+pub enum CacheError {
+    Sled(Error),
+    Repository(RepositoryError),
 }
 
 impl StdError for CacheError {}
@@ -20,80 +17,56 @@ impl StdError for CacheError {}
 impl fmt::Display for CacheError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            CacheError::SledError(err) => write!(f, "Sled error: {}", err),
-            CacheError::SerializationError(err) => write!(f, "Serialization error: {}", err),
-            CacheError::DeserializationError(err) => write!(f, "Deserialization error: {}", err),
-            CacheError::CacheError(msg) => write!(f, "Cache error: {}", msg),
+            CacheError::Sled(err) => write!(f, "Sled error: {}", err),
+            CacheError::Repository(err) => write!(f, "Repository error: {}", err),
         }
     }
 }
 
 impl From<Error> for CacheError {
     fn from(err: Error) -> Self {
-        CacheError::SledError(err)
+        CacheError::Sled(err)
     }
 }
 
-impl From<serde_json::Error> for CacheError {
-    fn from(err: serde_json::Error) -> Self {
-        CacheError::SerializationError(err)
+impl From<RepositoryError> for CacheError {
+    fn from(err: RepositoryError) -> Self {
+        CacheError::Repository(err)
     }
 }
 
 #[derive(Clone)]
 pub struct CacheWrapper {
-    db: Arc<RwLock<Db>>,
-    tables: Arc<RwLock<BTreeMap<String, Tree>>>,
+    db: Db,
 }
 
 impl CacheWrapper {
     // Initialize a new database
     pub fn new(path: &str) -> Result<Self, CacheError> {
         let db = sled::open(path)?;
-        let tables = Arc::new(RwLock::new(BTreeMap::new()));
-        Ok(CacheWrapper {
-            db: Arc::new(RwLock::new(db)),
-            tables,
-        })
+        Ok(CacheWrapper { db })
     }
 
-    // Create a new table (a new Tree in Sled) or directly access the table
-    pub fn get_table(&self, table_name: &str) -> Result<Tree, CacheError> {
-        let db = self.db.read().unwrap();
-        let mut tables = self.tables.write().unwrap();
-
-        // If the table is not cached, open it and insert it into the cache
-        if !tables.contains_key(table_name) {
-            let tree = db.open_tree(table_name)?;
-            tables.insert(table_name.to_string(), tree.clone());
-        }
-
-        Ok(tables.get(table_name).unwrap().clone())
+    // Open a table (a Tree in Sled) as a repository of `T` keyed by string
+    pub fn get_table<T: Serialize + DeserializeOwned>(&self, table_name: &str) -> Result<Repository<String, T>, CacheError> {
+        Ok(Repository::open(&self.db, table_name)?)
     }
 
-    // Insert a key-value pair into a specified table (Tree)
-    pub fn insert<T: Serialize>(&self, table_name: &str, key: &[u8], value: T) -> Result<(), CacheError> {
-        let tree = self.get_table(table_name)?;
-        let json_value = serde_json::to_vec(&value)?;
-        tree.insert(key, json_value.as_slice())?;
+    // Insert a key-value pair into a specified table
+    pub fn insert<T: Serialize + DeserializeOwned>(&self, table_name: &str, key: &str, value: T) -> Result<(), CacheError> {
+        self.get_table(table_name)?.put(&key.to_string(), &value)?;
         Ok(())
     }
 
-    // Remove a key-value pair from a specified table (Tree)
-    pub fn remove(&self, table_name: &str, key: &[u8]) -> Result<Option<IVec>, CacheError> {
-        let tree = self.get_table(table_name)?;
-        Ok(tree.remove(key)?)
+    // Remove a key-value pair from a specified table, returning the removed value
+    pub fn remove<T: Serialize + DeserializeOwned>(&self, table_name: &str, key: &str) -> Result<Option<T>, CacheError> {
+        Ok(self.get_table(table_name)?.delete(&key.to_string())?)
     }
 
-    // Find all key-value pairs in a specific table (Tree)
-    pub fn find<T: Deserialize<'static>>(&self, table_name: &str) -> Result<Vec<(Vec<u8>, T)>, CacheError> {
-        let tree = self.get_table(table_name)?;
-        let mut results = Vec::new();
-        for item in tree.iter() {
-            let (key, value) = item?;
-            let deserialized_value: T = serde_json::from_slice(&value.clone())?; // Cloned value to avoid lifetime issue
-            results.push((key.to_vec(), deserialized_value));
-        }
+    // Find all key-value pairs in a specific table
+    pub fn find<T: Serialize + DeserializeOwned>(&self, table_name: &str) -> Result<Vec<(String, T)>, CacheError> {
+        let table = self.get_table(table_name)?;
+        let results = table.iter().collect::<Result<Vec<_>, _>>()?;
         Ok(results)
     }
 }
@@ -108,20 +81,20 @@ fn main() -> Result<(), CacheError> {
     let db = CacheWrapper::new("my_db")?;
 
     // Insert data into the "users" table
-    db.insert("users", b"user1", User {
+    db.insert("users", "user1", User {
         name: "John Doe".to_string(),
         email: "john.doe@example.com".to_string(),
     })?;
 
     // Find all data in the "users" table
-    let all_users: Vec<(Vec<u8>, User)> = db.find("users")?;
+    let all_users: Vec<(String, User)> = db.find("users")?;
     println!("All users: {:?}", all_users);
 
     // Remove the "user1" entry from the "users" table
-    db.remove("users", b"user1")?;
+    db.remove::<User>("users", "user1")?;
 
     // Find all data in the "users" table after removal
-    let all_users_after_removal: Vec<(Vec<u8>, User)> = db.find("users")?;
+    let all_users_after_removal: Vec<(String, User)> = db.find("users")?;
     println!("All users after removal: {:?}", all_users_after_removal);
 
     Ok(())
@@ -0,0 +1,465 @@
+use codec::Codec;
+use sled::{Batch, Db, IVec, Tree};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TICK_LEN: usize = 8;
+const POLICY_LEN: usize = 24;
+
+/// Limits applied to a single cache table.
+///
+/// Without a TTL entries never expire; without `max_entries`/`max_bytes`
+/// the table is unbounded and access order is not tracked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TablePolicy {
+    pub ttl: Option<Duration>,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl TablePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.max_entries.is_some() || self.max_bytes.is_some()
+    }
+
+    /// Fixed-width encoding kept in the policy tree; zero means "not set".
+    pub(crate) fn to_bytes(self) -> [u8; POLICY_LEN] {
+        let mut bytes = [0; POLICY_LEN];
+        let ttl = self.ttl.map_or(0, |ttl| ttl.as_millis() as u64);
+        bytes[..8].copy_from_slice(&ttl.to_be_bytes());
+        bytes[8..16].copy_from_slice(&(self.max_entries.unwrap_or(0) as u64).to_be_bytes());
+        bytes[16..].copy_from_slice(&(self.max_bytes.unwrap_or(0) as u64).to_be_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.len() != POLICY_LEN {
+            return Self::default();
+        }
+        let field = |i: usize| {
+            let value = read_u64(&bytes[i * 8..]);
+            (value != 0).then_some(value)
+        };
+        TablePolicy {
+            ttl: field(0).map(Duration::from_millis),
+            max_entries: field(1).map(|n| n as usize),
+            max_bytes: field(2).map(|n| n as usize),
+        }
+    }
+}
+
+/// Point-in-time counters for one table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries removed because their TTL ran out.
+    pub expirations: u64,
+    /// Entries removed to respect `max_entries` or `max_bytes`.
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl CacheMetrics {
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// A cache table together with the trees that track its expiry and access order.
+///
+/// * `<name>.ttl`: key -> expiry time (ms since epoch)
+/// * `<name>.expiry`: expiry time ++ key, scanned in order by the sweeper
+/// * `<name>.access`: key -> access tick
+/// * `<name>.lru`: access tick ++ key, least recently used first
+///
+/// Writers hold `writes` so the five trees and the counters change together.
+pub(crate) struct Table {
+    data: Tree,
+    deadlines: Tree,
+    expiry: Tree,
+    access: Tree,
+    lru: Tree,
+    policy: RwLock<TablePolicy>,
+    codec: Codec,
+    writes: Mutex<()>,
+    entries: AtomicUsize,
+    bytes: AtomicUsize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expirations: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Table {
+    pub(crate) fn open(
+        db: &Db,
+        name: &str,
+        policy: TablePolicy,
+        codec: Codec,
+    ) -> sled::Result<Self> {
+        let data = db.open_tree(name)?;
+        let lru = db.open_tree(format!("{}.lru", name))?;
+
+        let mut entries = 0;
+        let mut bytes = 0;
+        for item in data.iter() {
+            let (key, value) = item?;
+            entries += 1;
+            bytes += key.len() + value.len();
+        }
+        let clock = match lru.last()? {
+            Some((tick, _)) => read_u64(&tick) + 1,
+            None => 0,
+        };
+
+        let table = Table {
+            deadlines: db.open_tree(format!("{}.ttl", name))?,
+            expiry: db.open_tree(format!("{}.expiry", name))?,
+            access: db.open_tree(format!("{}.access", name))?,
+            data,
+            lru,
+            policy: RwLock::new(policy),
+            codec,
+            writes: Mutex::new(()),
+            entries: AtomicUsize::new(entries),
+            bytes: AtomicUsize::new(bytes),
+            clock: AtomicU64::new(clock),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        };
+        if policy.is_bounded() {
+            table.track_untracked()?;
+        }
+        Ok(table)
+    }
+
+    pub(crate) fn tree(&self) -> &Tree {
+        &self.data
+    }
+
+    pub(crate) fn policy(&self) -> TablePolicy {
+        *self.policy.read().unwrap()
+    }
+
+    /// Codec used for the typed values of this table.
+    pub(crate) fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Replaces the policy and immediately enforces its size limits.
+    pub(crate) fn set_policy(&self, policy: TablePolicy) -> sled::Result<()> {
+        let _writes = self.writes.lock().unwrap();
+        *self.policy.write().unwrap() = policy;
+        match policy.ttl {
+            Some(ttl) => self.expire_untracked(ttl)?,
+            None => {
+                self.deadlines.clear()?;
+                self.expiry.clear()?;
+            }
+        }
+        if policy.is_bounded() {
+            self.track_untracked()?;
+        } else {
+            self.access.clear()?;
+            self.lru.clear()?;
+        }
+        self.enforce_limits(&policy)
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> sled::Result<Option<IVec>> {
+        let value = match self.data.get(key)? {
+            Some(value) => value,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        };
+
+        let policy = self.policy();
+        if policy.ttl.is_some() && self.is_expired(key, now_millis())? {
+            self.remove(key)?;
+            self.expirations.fetch_add(1, Ordering::Relaxed);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if policy.is_bounded() {
+            let _writes = self.writes.lock().unwrap();
+            if self.data.contains_key(key)? {
+                self.touch(key)?;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub(crate) fn insert(&self, key: &[u8], value: &[u8]) -> sled::Result<()> {
+        let _writes = self.writes.lock().unwrap();
+        let previous = self.data.insert(key, value)?;
+        self.account_insert(key, value.len(), previous.as_ref().map(|old| old.len()));
+
+        let policy = self.policy();
+        match policy.ttl {
+            Some(ttl) => self.set_deadline(key, now_millis() + ttl.as_millis() as u64)?,
+            None => self.clear_deadline(key)?,
+        }
+        if policy.is_bounded() {
+            self.touch(key)?;
+            self.enforce_limits(&policy)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `entries` with one batch per tree instead of one write per entry.
+    /// When a key appears more than once the last value wins.
+    pub(crate) fn insert_many(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> sled::Result<()> {
+        let entries: BTreeMap<Vec<u8>, Vec<u8>> = entries.into_iter().collect();
+        let _writes = self.writes.lock().unwrap();
+        let policy = self.policy();
+        let deadline = policy
+            .ttl
+            .map(|ttl| (now_millis() + ttl.as_millis() as u64).to_be_bytes());
+
+        let mut data = Batch::default();
+        let mut deadlines = Batch::default();
+        let mut expiry = Batch::default();
+        let mut access = Batch::default();
+        let mut lru = Batch::default();
+        let mut previous = Vec::with_capacity(entries.len());
+        for (key, value) in &entries {
+            previous.push(self.data.get(key)?.map(|old| old.len()));
+            data.insert(key.as_slice(), value.as_slice());
+
+            if let Some(old) = self.deadlines.get(key)? {
+                expiry.remove(prefixed(&old, key));
+            }
+            match &deadline {
+                Some(deadline) => {
+                    deadlines.insert(key.as_slice(), &deadline[..]);
+                    expiry.insert(prefixed(deadline, key), Vec::new());
+                }
+                None => deadlines.remove(key.as_slice()),
+            }
+
+            if policy.is_bounded() {
+                let tick = self.clock.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+                if let Some(old) = self.access.get(key)? {
+                    lru.remove(prefixed(&old, key));
+                }
+                access.insert(key.as_slice(), &tick[..]);
+                lru.insert(prefixed(&tick, key), Vec::new());
+            }
+        }
+
+        self.data.apply_batch(data)?;
+        self.deadlines.apply_batch(deadlines)?;
+        self.expiry.apply_batch(expiry)?;
+        self.access.apply_batch(access)?;
+        self.lru.apply_batch(lru)?;
+        for ((key, value), previous) in entries.iter().zip(previous) {
+            self.account_insert(key, value.len(), previous);
+        }
+        if policy.is_bounded() {
+            self.enforce_limits(&policy)?;
+        }
+        Ok(())
+    }
+
+    /// Removes `key` and its bookkeeping, returning whether it was present.
+    pub(crate) fn remove(&self, key: &[u8]) -> sled::Result<bool> {
+        let _writes = self.writes.lock().unwrap();
+        self.remove_entry(key)
+    }
+
+    fn remove_entry(&self, key: &[u8]) -> sled::Result<bool> {
+        let previous = self.data.remove(key)?;
+        if let Some(old) = &previous {
+            self.entries.fetch_sub(1, Ordering::Relaxed);
+            self.bytes
+                .fetch_sub(key.len() + old.len(), Ordering::Relaxed);
+        }
+        self.clear_deadline(key)?;
+        if let Some(tick) = self.access.remove(key)? {
+            self.lru.remove(prefixed(&tick, key))?;
+        }
+        Ok(previous.is_some())
+    }
+
+    /// Drops every entry whose TTL has run out and returns how many were removed.
+    pub(crate) fn sweep(&self) -> sled::Result<usize> {
+        let now = now_millis();
+        let mut removed = 0;
+        for item in self.expiry.iter() {
+            let (entry, _) = item?;
+            if read_u64(&entry) > now {
+                break;
+            }
+            let key = &entry[TICK_LEN..];
+            if self.is_expired(key, now)? && self.remove(key)? {
+                removed += 1;
+            } else {
+                self.expiry.remove(&entry)?;
+            }
+        }
+        self.expirations
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    pub(crate) fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn account_insert(&self, key: &[u8], len: usize, previous: Option<usize>) {
+        match previous {
+            Some(old) => {
+                self.bytes.fetch_sub(old, Ordering::Relaxed);
+                self.bytes.fetch_add(len, Ordering::Relaxed);
+            }
+            None => {
+                self.entries.fetch_add(1, Ordering::Relaxed);
+                self.bytes.fetch_add(key.len() + len, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn is_expired(&self, key: &[u8], now: u64) -> sled::Result<bool> {
+        Ok(match self.deadlines.get(key)? {
+            Some(deadline) => read_u64(&deadline) <= now,
+            None => false,
+        })
+    }
+
+    fn set_deadline(&self, key: &[u8], deadline: u64) -> sled::Result<()> {
+        let deadline = deadline.to_be_bytes();
+        if let Some(old) = self.deadlines.insert(key, &deadline)? {
+            self.expiry.remove(prefixed(&old, key))?;
+        }
+        self.expiry.insert(prefixed(&deadline, key), &[])?;
+        Ok(())
+    }
+
+    fn clear_deadline(&self, key: &[u8]) -> sled::Result<()> {
+        if let Some(old) = self.deadlines.remove(key)? {
+            self.expiry.remove(prefixed(&old, key))?;
+        }
+        Ok(())
+    }
+
+    fn touch(&self, key: &[u8]) -> sled::Result<()> {
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed).to_be_bytes();
+        if let Some(old) = self.access.insert(key, &tick)? {
+            self.lru.remove(prefixed(&old, key))?;
+        }
+        self.lru.insert(prefixed(&tick, key), &[])?;
+        Ok(())
+    }
+
+    fn over_limits(&self, policy: &TablePolicy) -> bool {
+        policy
+            .max_entries
+            .is_some_and(|max| self.entries.load(Ordering::Relaxed) > max)
+            || policy
+                .max_bytes
+                .is_some_and(|max| self.bytes.load(Ordering::Relaxed) > max)
+    }
+
+    /// Evicts least recently used entries until the table fits its policy.
+    fn enforce_limits(&self, policy: &TablePolicy) -> sled::Result<()> {
+        while self.over_limits(policy) {
+            let entry = match self.lru.first()? {
+                Some((entry, _)) => entry,
+                None => break,
+            };
+            if self.remove_entry(&entry[TICK_LEN..])? {
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            } else {
+                // Left behind by a key that is already gone.
+                self.lru.remove(&entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the TTL of entries written while the table had none.
+    fn expire_untracked(&self, ttl: Duration) -> sled::Result<()> {
+        let deadline = now_millis() + ttl.as_millis() as u64;
+        for item in self.data.iter().keys() {
+            let key = item?;
+            if !self.deadlines.contains_key(&key)? {
+                self.set_deadline(&key, deadline)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives entries written while the table was unbounded (or straight through
+    /// the tree) an access tick so they take part in eviction.
+    fn track_untracked(&self) -> sled::Result<()> {
+        for item in self.data.iter().keys() {
+            let key = item?;
+            if !self.access.contains_key(&key)? {
+                self.touch(&key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(prefix.len() + key.len());
+    entry.extend_from_slice(prefix);
+    entry.extend_from_slice(key);
+    entry
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; TICK_LEN];
+    buf.copy_from_slice(&bytes[..TICK_LEN]);
+    u64::from_be_bytes(buf)
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
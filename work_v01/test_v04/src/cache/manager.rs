@@ -0,0 +1,474 @@
+use super::eviction::{CacheMetrics, Table, TablePolicy};
+use super::scan::{decode_token, encode_token, CacheError, Entries, Page, TableStats};
+use super::snapshot::{export_trees, import_trees, SnapshotError, SnapshotFormat, SnapshotSummary};
+use codec::Codec;
+use repository::{Key, Repository, RepositoryError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::{Db, Tree};
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const POLICY_TREE: &str = "__cache_policies";
+const CODEC_TREE: &str = "__cache_codecs";
+const BOOKKEEPING_SUFFIXES: [&str; 4] = [".ttl", ".expiry", ".access", ".lru"];
+
+type Tables = Arc<Mutex<HashMap<String, Arc<Table>>>>;
+
+/// Handle to a sled-backed cache. Clones share the same database and open
+/// tables, so one manager can be handed to every task that needs it.
+#[derive(Clone)]
+pub struct CacheManager {
+    db: Db,
+    policies: Tree,
+    codecs: Tree,
+    /// Codec recorded for tables opened before one was chosen for them.
+    codec: Codec,
+    tables: Tables,
+    /// Held shared by every write and exclusively by snapshots, so an export
+    /// sees a single point in time.
+    gate: Arc<RwLock<()>>,
+}
+
+impl CacheManager {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, sled::Error> {
+        let db = sled::open(path)?;
+        let policies = db.open_tree(POLICY_TREE)?;
+        let codecs = db.open_tree(CODEC_TREE)?;
+        Ok(CacheManager {
+            db,
+            policies,
+            codecs,
+            codec: Codec::default(),
+            tables: Arc::new(Mutex::new(HashMap::new())),
+            gate: Arc::new(RwLock::new(())),
+        })
+    }
+
+    /// Encodes the typed values of new tables with `codec` instead of JSON.
+    /// Tables that already have a codec recorded keep it.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn get_or_create_table(&self, name: &str) -> Result<Tree, sled::Error> {
+        Ok(self.table(name)?.tree().clone())
+    }
+
+    fn table(&self, name: &str) -> Result<Arc<Table>, sled::Error> {
+        let mut tables = self.tables.lock().unwrap();
+        if let Some(table) = tables.get(name) {
+            return Ok(table.clone());
+        }
+        let policy = match self.policies.get(name)? {
+            Some(bytes) => TablePolicy::from_bytes(&bytes),
+            None => TablePolicy::default(),
+        };
+        let codec = match self.codecs.get(name)? {
+            Some(value) => String::from_utf8_lossy(&value)
+                .parse()
+                .map_err(sled::Error::Unsupported)?,
+            None => {
+                self.codecs.insert(name, self.codec.name())?;
+                self.codec
+            }
+        };
+        let table = Arc::new(Table::open(&self.db, name, policy, codec)?);
+        tables.insert(name.to_string(), table.clone());
+        Ok(table)
+    }
+
+    /// Sets the TTL and size limits of `name`; the policy is persisted and
+    /// applies to entries already in the table.
+    pub async fn set_table_policy(
+        &self,
+        name: &str,
+        policy: TablePolicy,
+    ) -> Result<(), sled::Error> {
+        let name = name.to_string();
+        self.blocking(move |manager| {
+            manager.policies.insert(name.as_str(), &policy.to_bytes())?;
+            manager.table(&name)?.set_policy(policy)
+        })
+        .await
+    }
+
+    /// Encodes the typed values of `name` with `codec` from now on. Fails if
+    /// the table already holds entries written with another codec.
+    pub async fn set_table_codec(&self, name: &str, codec: Codec) -> Result<(), CacheError> {
+        let name = name.to_string();
+        self.blocking(move |manager| Ok(manager.record_codec(&name, codec)))
+            .await?
+    }
+
+    fn record_codec(&self, name: &str, codec: Codec) -> Result<(), CacheError> {
+        let table = self.table(name)?;
+        if table.codec() == codec {
+            return Ok(());
+        }
+        if table.metrics().entries > 0 {
+            return Err(CacheError::CodecMismatch {
+                table: name.to_string(),
+                recorded: table.codec(),
+                requested: codec,
+            });
+        }
+        self.codecs.insert(name, codec.name())?;
+        self.tables.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    pub fn table_policy(&self, name: &str) -> Result<TablePolicy, sled::Error> {
+        Ok(self.table(name)?.policy())
+    }
+
+    /// Hit, miss and eviction counters of `name` since it was opened.
+    pub fn metrics(&self, name: &str) -> Option<CacheMetrics> {
+        let tables = self.tables.lock().unwrap();
+        tables.get(name).map(|table| table.metrics())
+    }
+
+    /// Starts a task that removes expired entries from every open table each
+    /// `interval`. Each sweep runs on tokio's blocking pool.
+    pub fn spawn_sweeper(&self, interval: Duration) -> JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let manager = manager.clone();
+                let swept = tokio::task::spawn_blocking(move || {
+                    let open: Vec<Arc<Table>> =
+                        manager.tables.lock().unwrap().values().cloned().collect();
+                    for table in open {
+                        let _guard = manager.gate.read().unwrap();
+                        if let Err(err) = table.sweep() {
+                            eprintln!("Error sweeping expired cache entries: {}", err);
+                        }
+                    }
+                })
+                .await;
+                if let Err(err) = swept {
+                    eprintln!("Cache sweeper failed: {}", err);
+                }
+            }
+        })
+    }
+
+    /// Names of the cache tables in the database, leaving out sled's default
+    /// tree and the trees that hold TTL and access bookkeeping.
+    pub fn table_names(&self) -> Vec<String> {
+        let names: Vec<String> = self
+            .db
+            .tree_names()
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+        names
+            .iter()
+            .filter(|name| !name.starts_with("__"))
+            .filter(|name| {
+                !BOOKKEEPING_SUFFIXES.iter().any(|suffix| {
+                    name.strip_suffix(suffix)
+                        .is_some_and(|base| names.iter().any(|other| other == base))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn stats(&self, name: &str) -> Result<TableStats, sled::Error> {
+        let table = self.table(name)?;
+        let metrics = table.metrics();
+        Ok(TableStats {
+            name: name.to_string(),
+            entries: metrics.entries,
+            bytes: metrics.bytes,
+            policy: table.policy(),
+        })
+    }
+
+    pub fn iter(&self, name: &str) -> Result<Entries, sled::Error> {
+        let table = self.table(name)?;
+        let inner = table.tree().iter();
+        Ok(Entries::new(table, inner))
+    }
+
+    pub fn scan_prefix<P: AsRef<[u8]>>(
+        &self,
+        name: &str,
+        prefix: P,
+    ) -> Result<Entries, sled::Error> {
+        let table = self.table(name)?;
+        let inner = table.tree().scan_prefix(prefix);
+        Ok(Entries::new(table, inner))
+    }
+
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(
+        &self,
+        name: &str,
+        range: R,
+    ) -> Result<Entries, sled::Error> {
+        let table = self.table(name)?;
+        let inner = table.tree().range(range);
+        Ok(Entries::new(table, inner))
+    }
+
+    /// Returns up to `limit` entries starting with `prefix`, continuing after
+    /// the page that produced `token` when one is given.
+    pub fn page<P: AsRef<[u8]>>(
+        &self,
+        name: &str,
+        prefix: P,
+        token: Option<&str>,
+        limit: usize,
+    ) -> Result<Page, CacheError> {
+        let prefix = prefix.as_ref();
+        let start = match token {
+            Some(token) => {
+                let last = decode_token(token)?;
+                if !last.starts_with(prefix) {
+                    return Err(CacheError::InvalidToken(token.to_string()));
+                }
+                Bound::Excluded(last)
+            }
+            None => Bound::Included(prefix.to_vec()),
+        };
+
+        let mut items = Vec::with_capacity(limit);
+        let mut more = false;
+        let entries = self.range(name, (start, Bound::<Vec<u8>>::Unbounded))?;
+        for item in entries {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            if items.len() == limit {
+                more = true;
+                break;
+            }
+            items.push((key.to_vec(), value.to_vec()));
+        }
+
+        let next = match items.last() {
+            Some((key, _)) if more => Some(encode_token(key)),
+            _ => None,
+        };
+        Ok(Page { items, next })
+    }
+
+    /// Opens `name` as a typed table sharing this cache's database.
+    pub fn repository<K: Key, V: Serialize + DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<Repository<K, V>, RepositoryError> {
+        Repository::open(&self.db, name)
+    }
+
+    /// Runs `f` on tokio's blocking pool; sled calls may touch the disk.
+    async fn blocking<T, F>(&self, f: F) -> Result<T, sled::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&CacheManager) -> Result<T, sled::Error> + Send + 'static,
+    {
+        let manager = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = manager.gate.read().unwrap();
+            f(&manager)
+        })
+        .await
+        .map_err(|e| sled::Error::Io(std::io::Error::other(e)))?
+    }
+
+    /// Writes `tables` (with their TTL and access bookkeeping, policy and
+    /// codec), or the whole database when `tables` is `None`, to `path`.
+    /// Writers wait until the export is done so the file is a consistent
+    /// point-in-time view.
+    pub async fn export_snapshot<P: Into<PathBuf>>(
+        &self,
+        path: P,
+        format: SnapshotFormat,
+        tables: Option<&[&str]>,
+    ) -> Result<SnapshotSummary, SnapshotError> {
+        let path = path.into();
+        let mut all: Vec<String> = self
+            .db
+            .tree_names()
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+        all.sort();
+        let shared: Vec<(String, Vec<u8>)> = tables
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|table| {
+                [POLICY_TREE, CODEC_TREE].map(|tree| (tree.to_string(), table.as_bytes().to_vec()))
+            })
+            .collect();
+        let trees = match tables {
+            None => all,
+            Some(tables) => tables
+                .iter()
+                .flat_map(|table| {
+                    let mut names = vec![table.to_string()];
+                    names.extend(
+                        BOOKKEEPING_SUFFIXES
+                            .iter()
+                            .map(|suffix| format!("{}{}", table, suffix))
+                            .filter(|name| all.contains(name)),
+                    );
+                    names
+                })
+                .collect(),
+        };
+
+        let manager = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = manager.gate.write().unwrap();
+            export_trees(&manager.db, &trees, &shared, path, format)
+        })
+        .await
+        .map_err(|e| SnapshotError::Io(std::io::Error::other(e)))?
+    }
+
+    /// Replaces the trees contained in the snapshot at `path`. Open tables are
+    /// dropped afterwards so counters and policies are reloaded from disk.
+    pub async fn import_snapshot<P: Into<PathBuf>>(
+        &self,
+        path: P,
+        format: SnapshotFormat,
+    ) -> Result<SnapshotSummary, SnapshotError> {
+        let path = path.into();
+        let manager = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = manager.gate.write().unwrap();
+            let summary = import_trees(&manager.db, path, format)?;
+            manager.tables.lock().unwrap().clear();
+            Ok(summary)
+        })
+        .await
+        .map_err(|e| SnapshotError::Io(std::io::Error::other(e)))?
+    }
+
+    pub async fn insert_async<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        table: &str,
+        key: K,
+        value: V,
+    ) -> Result<(), sled::Error> {
+        let table = table.to_string();
+        let key = key.as_ref().to_vec();
+        let value = value.as_ref().to_vec();
+        self.blocking(move |manager| manager.table(&table)?.insert(&key, &value))
+            .await
+    }
+
+    /// Inserts every entry of `items` into `table` as a single batch.
+    pub async fn insert_many<K, V, I>(&self, table: &str, items: I) -> Result<(), sled::Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let table = table.to_string();
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = items
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_vec(), value.as_ref().to_vec()))
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.blocking(move |manager| manager.table(&table)?.insert_many(entries))
+            .await
+    }
+
+    /// Codec the typed values of `table` are encoded with.
+    pub async fn table_codec(&self, table: &str) -> Result<Codec, sled::Error> {
+        let table = table.to_string();
+        self.blocking(move |manager| Ok(manager.table(&table)?.codec()))
+            .await
+    }
+
+    /// Reads `key` and decodes it with the table's codec.
+    pub async fn get<T: DeserializeOwned, K: AsRef<[u8]>>(
+        &self,
+        table: &str,
+        key: K,
+    ) -> Result<Option<T>, CacheError> {
+        let table = table.to_string();
+        let key = key.as_ref().to_vec();
+        let (codec, value) = self
+            .blocking(move |manager| {
+                let table = manager.table(&table)?;
+                Ok((table.codec(), table.get(&key)?))
+            })
+            .await?;
+        match value {
+            Some(bytes) => Ok(Some(codec.decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `value` under `key` encoded with the table's codec.
+    pub async fn put<T: Serialize, K: AsRef<[u8]>>(
+        &self,
+        table: &str,
+        key: K,
+        value: &T,
+    ) -> Result<(), CacheError> {
+        let bytes = self.table_codec(table).await?.encode(value)?;
+        Ok(self.insert_async(table, key, bytes).await?)
+    }
+
+    pub async fn get_async<K: AsRef<[u8]>>(
+        &self,
+        table: &str,
+        key: K,
+    ) -> Result<Option<Vec<u8>>, sled::Error> {
+        let table = table.to_string();
+        let key = key.as_ref().to_vec();
+        self.blocking(move |manager| {
+            let value = manager.table(&table)?.get(&key)?;
+            Ok(value.map(|ivec| ivec.to_vec()))
+        })
+        .await
+    }
+
+    /// Looks up every key in `keys`, returning the values in the same order.
+    pub async fn get_many<K, I>(
+        &self,
+        table: &str,
+        keys: I,
+    ) -> Result<Vec<Option<Vec<u8>>>, sled::Error>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        let table = table.to_string();
+        let keys: Vec<Vec<u8>> = keys.into_iter().map(|key| key.as_ref().to_vec()).collect();
+        self.blocking(move |manager| {
+            let table = manager.table(&table)?;
+            keys.iter()
+                .map(|key| Ok(table.get(key)?.map(|ivec| ivec.to_vec())))
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn delete_async<K: AsRef<[u8]>>(
+        &self,
+        table: &str,
+        key: K,
+    ) -> Result<(), sled::Error> {
+        let table = table.to_string();
+        let key = key.as_ref().to_vec();
+        self.blocking(move |manager| manager.table(&table)?.remove(&key).map(|_| ()))
+            .await
+    }
+}
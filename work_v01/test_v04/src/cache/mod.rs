@@ -1 +1,4 @@
-pub mod cache;
+pub mod eviction;
+pub mod manager;
+pub mod scan;
+pub mod snapshot;
@@ -0,0 +1,149 @@
+use super::eviction::{now_millis, Table, TablePolicy};
+use codec::{Codec, CodecError};
+use serde::de::DeserializeOwned;
+use sled::IVec;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Sled(sled::Error),
+    Codec(CodecError),
+    InvalidToken(String),
+    /// The table already holds entries written with another codec.
+    CodecMismatch {
+        table: String,
+        recorded: Codec,
+        requested: Codec,
+    },
+}
+
+impl std::error::Error for CacheError {}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Sled(e) => write!(f, "Sled error: {}", e),
+            CacheError::Codec(e) => write!(f, "Codec error: {}", e),
+            CacheError::InvalidToken(token) => write!(f, "Invalid continuation token: {}", token),
+            CacheError::CodecMismatch {
+                table,
+                recorded,
+                requested,
+            } => write!(
+                f,
+                "Table {} is encoded with {}, not {}",
+                table, recorded, requested
+            ),
+        }
+    }
+}
+
+impl From<sled::Error> for CacheError {
+    fn from(e: sled::Error) -> Self {
+        CacheError::Sled(e)
+    }
+}
+
+impl From<CodecError> for CacheError {
+    fn from(e: CodecError) -> Self {
+        CacheError::Codec(e)
+    }
+}
+
+/// Lazy iterator over the live entries of a table; expired entries that the
+/// sweeper has not removed yet are skipped.
+pub struct Entries {
+    table: Arc<Table>,
+    inner: sled::Iter,
+    check_expiry: bool,
+    now: u64,
+}
+
+impl Entries {
+    pub(crate) fn new(table: Arc<Table>, inner: sled::Iter) -> Self {
+        let check_expiry = table.policy().ttl.is_some();
+        Entries {
+            table,
+            inner,
+            check_expiry,
+            now: now_millis(),
+        }
+    }
+
+    /// Decodes every value into `T` with the table's codec.
+    pub fn decode<T: DeserializeOwned>(
+        self,
+    ) -> impl Iterator<Item = Result<(IVec, T), CacheError>> {
+        let codec = self.table.codec();
+        self.map(move |item| {
+            let (key, value) = item?;
+            Ok((key, codec.decode(&value)?))
+        })
+    }
+}
+
+impl Iterator for Entries {
+    type Item = Result<(IVec, IVec), sled::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.check_expiry {
+                match self.table.is_expired(&key, self.now) {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            return Some(Ok((key, value)));
+        }
+    }
+}
+
+/// One page of a prefix scan. `next` is `None` on the last page; otherwise it
+/// is passed back to `CacheManager::page` to continue after this page.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub items: Vec<(Vec<u8>, Vec<u8>)>,
+    pub next: Option<String>,
+}
+
+/// Size of a table as tracked by the cache, without scanning it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+    pub name: String,
+    pub entries: usize,
+    /// Key and value bytes, excluding sled's own overhead.
+    pub bytes: usize,
+    pub policy: TablePolicy,
+}
+
+/// Continuation tokens are the hex encoded last key of the previous page.
+pub(crate) fn encode_token(key: &[u8]) -> String {
+    to_hex(key)
+}
+
+pub(crate) fn decode_token(token: &str) -> Result<Vec<u8>, CacheError> {
+    from_hex(token).ok_or_else(|| CacheError::InvalidToken(token.to_string()))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
@@ -0,0 +1,455 @@
+use super::scan::{from_hex, to_hex};
+use arrow::array::{
+    Array, ArrayBuilder, ArrayRef, BinaryArray, BinaryBuilder, StringArray, StringBuilder,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use sled::{Batch, Db, IVec, Tree};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SNAPSHOT_VERSION: u32 = 1;
+const BATCH_ROWS: usize = 1024;
+const STAGING_PREFIX: &str = "__snapshot_staging.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// A header line followed by one `{"tree", "key", "value"}` object per
+    /// entry, keys and values hex encoded.
+    JsonLines,
+    /// An Arrow IPC file with `tree`, `key` and `value` columns.
+    ArrowIpc,
+}
+
+impl SnapshotFormat {
+    /// Picks the format from the file extension (`.jsonl`/`.json` or `.arrow`/`.ipc`).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "jsonl" | "json" => Ok(SnapshotFormat::JsonLines),
+            "arrow" | "ipc" => Ok(SnapshotFormat::ArrowIpc),
+            _ => Err(format!("unknown snapshot format: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Sled(sled::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Arrow(ArrowError),
+    Format(String),
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Sled(e) => write!(f, "Sled error: {}", e),
+            SnapshotError::Io(e) => write!(f, "IO error: {}", e),
+            SnapshotError::Json(e) => write!(f, "Serde JSON error: {}", e),
+            SnapshotError::Arrow(e) => write!(f, "Arrow error: {}", e),
+            SnapshotError::Format(msg) => write!(f, "Invalid snapshot: {}", msg),
+        }
+    }
+}
+
+impl From<sled::Error> for SnapshotError {
+    fn from(e: sled::Error) -> Self {
+        SnapshotError::Sled(e)
+    }
+}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+impl From<ArrowError> for SnapshotError {
+    fn from(e: ArrowError) -> Self {
+        SnapshotError::Arrow(e)
+    }
+}
+
+/// What an export wrote or an import restored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotSummary {
+    pub created_at: u64,
+    pub trees: Vec<String>,
+    pub entries: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    snapshot: u32,
+    created_at: u64,
+    trees: Vec<String>,
+    /// Trees of which only some entries were exported; they are merged into
+    /// the existing tree on import instead of replacing it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    tree: String,
+    key: String,
+    value: String,
+}
+
+/// Writes every entry of `trees`, and the single `(tree, key)` entries of
+/// `shared`, to `path`.
+///
+/// The caller is responsible for keeping writers out while this runs if the
+/// file has to be a point-in-time view; `CacheManager::export_snapshot` does.
+pub fn export_trees<P: AsRef<Path>>(
+    db: &Db,
+    trees: &[String],
+    shared: &[(String, Vec<u8>)],
+    path: P,
+    format: SnapshotFormat,
+) -> Result<SnapshotSummary, SnapshotError> {
+    let mut summary = SnapshotSummary {
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        trees: trees.to_vec(),
+        entries: 0,
+    };
+    let opened = trees
+        .iter()
+        .map(|name| Ok((name.as_str(), db.open_tree(name)?)))
+        .collect::<Result<Vec<(&str, Tree)>, sled::Error>>()?;
+    let singles = shared
+        .iter()
+        .map(|(name, key)| Ok((name.as_str(), db.open_tree(name)?, key)))
+        .collect::<Result<Vec<(&str, Tree, &Vec<u8>)>, sled::Error>>()?;
+    let mut merged: Vec<String> = shared.iter().map(|(name, _)| name.clone()).collect();
+    merged.sort();
+    merged.dedup();
+
+    let whole = opened.iter().flat_map(|(name, tree)| {
+        tree.iter()
+            .map(move |item| item.map(|(key, value)| (*name, key, value)))
+    });
+    let single = singles.iter().filter_map(|(name, tree, key)| {
+        tree.get(key)
+            .transpose()
+            .map(|item| item.map(|value| (*name, IVec::from(key.as_slice()), value)))
+    });
+    let records = whole.chain(single);
+
+    summary.entries = match format {
+        SnapshotFormat::JsonLines => write_json_lines(records, &summary, &merged, path)?,
+        SnapshotFormat::ArrowIpc => write_arrow(records, &summary, &merged, path)?,
+    };
+    Ok(summary)
+}
+
+/// Replaces the trees listed in the snapshot at `path` with its contents.
+/// Trees that are not part of the snapshot are left alone.
+///
+/// The file is read into staging trees first, so a snapshot that turns out
+/// to be corrupt halfway through leaves the live trees untouched.
+pub fn import_trees<P: AsRef<Path>>(
+    db: &Db,
+    path: P,
+    format: SnapshotFormat,
+) -> Result<SnapshotSummary, SnapshotError> {
+    let mut loader = Loader::default();
+    let loaded = match format {
+        SnapshotFormat::JsonLines => read_json_lines(db, &mut loader, path),
+        SnapshotFormat::ArrowIpc => read_arrow(db, &mut loader, path),
+    };
+    match loaded {
+        Ok(()) => loader.finish(db),
+        Err(e) => {
+            loader.discard(db)?;
+            Err(e)
+        }
+    }
+}
+
+fn write_json_lines<'a, P: AsRef<Path>>(
+    records: impl Iterator<Item = sled::Result<(&'a str, IVec, IVec)>>,
+    summary: &SnapshotSummary,
+    merged: &[String],
+    path: P,
+) -> Result<usize, SnapshotError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let header = Header {
+        snapshot: SNAPSHOT_VERSION,
+        created_at: summary.created_at,
+        trees: summary.trees.clone(),
+        merged: merged.to_vec(),
+    };
+    serde_json::to_writer(&mut writer, &header)?;
+    writeln!(writer)?;
+
+    let mut entries = 0;
+    for item in records {
+        let (name, key, value) = item?;
+        let record = Record {
+            tree: name.to_string(),
+            key: to_hex(&key),
+            value: to_hex(&value),
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)?;
+        entries += 1;
+    }
+    writer.flush()?;
+    Ok(entries)
+}
+
+fn write_arrow<'a, P: AsRef<Path>>(
+    records: impl Iterator<Item = sled::Result<(&'a str, IVec, IVec)>>,
+    summary: &SnapshotSummary,
+    merged: &[String],
+    path: P,
+) -> Result<usize, SnapshotError> {
+    let metadata = HashMap::from([
+        ("snapshot".to_string(), SNAPSHOT_VERSION.to_string()),
+        ("created_at".to_string(), summary.created_at.to_string()),
+        ("trees".to_string(), serde_json::to_string(&summary.trees)?),
+        ("merged".to_string(), serde_json::to_string(merged)?),
+    ]);
+    let schema = Arc::new(arrow_schema().with_metadata(metadata));
+    let mut writer = FileWriter::try_new(BufWriter::new(File::create(path)?), &schema)?;
+
+    let mut entries = 0;
+    let mut names = StringBuilder::new();
+    let mut keys = BinaryBuilder::new();
+    let mut values = BinaryBuilder::new();
+    for item in records {
+        let (name, key, value) = item?;
+        names.append_value(name);
+        keys.append_value(&key);
+        values.append_value(&value);
+        entries += 1;
+        if names.len() == BATCH_ROWS {
+            writer.write(&to_batch(&schema, &mut names, &mut keys, &mut values)?)?;
+        }
+    }
+    if !names.is_empty() {
+        writer.write(&to_batch(&schema, &mut names, &mut keys, &mut values)?)?;
+    }
+    writer.finish()?;
+    Ok(entries)
+}
+
+fn arrow_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("tree", DataType::Utf8, false),
+        Field::new("key", DataType::Binary, false),
+        Field::new("value", DataType::Binary, false),
+    ])
+}
+
+fn to_batch(
+    schema: &Arc<Schema>,
+    names: &mut StringBuilder,
+    keys: &mut BinaryBuilder,
+    values: &mut BinaryBuilder,
+) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(names.finish()),
+        Arc::new(keys.finish()),
+        Arc::new(values.finish()),
+    ];
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+fn read_json_lines<P: AsRef<Path>>(
+    db: &Db,
+    loader: &mut Loader,
+    path: P,
+) -> Result<(), SnapshotError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?)?,
+        None => return Err(SnapshotError::Format("empty file".to_string())),
+    };
+    check_version(header.snapshot)?;
+    loader.start(db, header.created_at, header.trees, header.merged)?;
+
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line)?;
+        let invalid = || SnapshotError::Format(format!("line {}: invalid hex", number + 2));
+        let key = from_hex(&record.key).ok_or_else(invalid)?;
+        let value = from_hex(&record.value).ok_or_else(invalid)?;
+        loader.insert(&record.tree, key, value)?;
+    }
+    Ok(())
+}
+
+fn read_arrow<P: AsRef<Path>>(db: &Db, loader: &mut Loader, path: P) -> Result<(), SnapshotError> {
+    let reader = FileReader::try_new(BufReader::new(File::open(path)?), None)?;
+    let schema = reader.schema();
+    let metadata = schema.metadata();
+    let field = |name: &str| {
+        metadata
+            .get(name)
+            .ok_or_else(|| SnapshotError::Format(format!("missing {} metadata", name)))
+    };
+    let version = field("snapshot")?
+        .parse()
+        .map_err(|_| SnapshotError::Format("invalid snapshot version".to_string()))?;
+    check_version(version)?;
+    let created_at = field("created_at")?
+        .parse()
+        .map_err(|_| SnapshotError::Format("invalid created_at".to_string()))?;
+    let trees = serde_json::from_str(field("trees")?)?;
+    let merged = match metadata.get("merged") {
+        Some(merged) => serde_json::from_str(merged)?,
+        None => Vec::new(),
+    };
+    loader.start(db, created_at, trees, merged)?;
+
+    for batch in reader {
+        let batch = batch?;
+        let names = column::<StringArray>(&batch, "tree")?;
+        let keys = column::<BinaryArray>(&batch, "key")?;
+        let values = column::<BinaryArray>(&batch, "value")?;
+        for row in 0..batch.num_rows() {
+            loader.insert(
+                names.value(row),
+                keys.value(row).to_vec(),
+                values.value(row).to_vec(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a T, SnapshotError> {
+    batch
+        .column_by_name(name)
+        .and_then(|column| column.as_any().downcast_ref::<T>())
+        .ok_or_else(|| SnapshotError::Format(format!("missing or mistyped {} column", name)))
+}
+
+fn check_version(version: u32) -> Result<(), SnapshotError> {
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::Format(format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+/// Fills one staging tree per snapshot tree in batches. The live trees are
+/// only touched by `finish`, once the whole file has been read.
+#[derive(Default)]
+struct Loader {
+    summary: SnapshotSummary,
+    /// Staging tree, pending batch and its size, and whether the live tree
+    /// is replaced (rather than merged into) when the load finishes.
+    trees: HashMap<String, (Tree, Batch, usize, bool)>,
+}
+
+impl Loader {
+    fn start(
+        &mut self,
+        db: &Db,
+        created_at: u64,
+        names: Vec<String>,
+        merged: Vec<String>,
+    ) -> Result<(), SnapshotError> {
+        let replaced = names.iter().map(|name| (name, true));
+        for (name, replace) in replaced.chain(merged.iter().map(|name| (name, false))) {
+            let staging = db.open_tree(staging_name(name))?;
+            staging.clear()?;
+            self.trees
+                .insert(name.clone(), (staging, Batch::default(), 0, replace));
+        }
+        self.summary.created_at = created_at;
+        self.summary.trees = names;
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, key: Vec<u8>, value: Vec<u8>) -> Result<(), SnapshotError> {
+        let (staging, batch, pending, _) = self.trees.get_mut(name).ok_or_else(|| {
+            SnapshotError::Format(format!("entry for tree {} not listed in header", name))
+        })?;
+        batch.insert(key, value);
+        *pending += 1;
+        self.summary.entries += 1;
+        if *pending == BATCH_ROWS {
+            staging.apply_batch(std::mem::take(batch))?;
+            *pending = 0;
+        }
+        Ok(())
+    }
+
+    /// Moves every staging tree into its live tree and drops the staging trees.
+    fn finish(mut self, db: &Db) -> Result<SnapshotSummary, SnapshotError> {
+        for (name, (staging, batch, _, replace)) in self.trees.iter_mut() {
+            staging.apply_batch(std::mem::take(batch))?;
+            let tree = db.open_tree(name)?;
+            if *replace {
+                tree.clear()?;
+            }
+            let mut pending = Batch::default();
+            let mut count = 0;
+            for item in staging.iter() {
+                let (key, value) = item?;
+                pending.insert(key, value);
+                count += 1;
+                if count == BATCH_ROWS {
+                    tree.apply_batch(std::mem::take(&mut pending))?;
+                    count = 0;
+                }
+            }
+            tree.apply_batch(pending)?;
+            tree.flush()?;
+            db.drop_tree(staging_name(name))?;
+        }
+        Ok(self.summary)
+    }
+
+    /// Drops the staging trees of a load that failed.
+    fn discard(self, db: &Db) -> Result<(), SnapshotError> {
+        for name in self.trees.keys() {
+            db.drop_tree(staging_name(name))?;
+        }
+        Ok(())
+    }
+}
+
+fn staging_name(tree: &str) -> String {
+    format!("{}{}", STAGING_PREFIX, tree)
+}
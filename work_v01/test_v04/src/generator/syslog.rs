@@ -28,6 +28,12 @@ pub struct SyslogMessageBatch {
     buffer: HashMap<DateTime<Utc>, SyslogMessage>,
 }
 
+impl Default for SyslogMessageBatch {
+    fn default() -> Self {
+        SyslogMessageBatch::new()
+    }
+}
+
 impl SyslogMessageBatch {
     // Constructor for SyslogMessageBatch that calls load
     pub fn new() -> Self {
@@ -1,20 +1,22 @@
-mod cache;
-mod generator;
-use cache::cache::CacheManager;
 use chrono::{DateTime, Utc};
-use generator::syslog::{SyslogMessage, SyslogMessageBatch};
 use serde::Serialize;
-use serde_json;
 use serde_json::Value;
-use sled::IVec;
 use std::collections::HashMap;
-use std::time::Instant;
-use tokio;
+use std::time::{Duration, Instant};
+use test_v04::cache::eviction::TablePolicy;
+use test_v04::cache::manager::CacheManager;
+use test_v04::cache::snapshot::{SnapshotError, SnapshotFormat};
+use test_v04::generator::syslog::{SyslogMessage, SyslogMessageBatch};
+
+const SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+const MAX_SESSIONS: usize = 100_000;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
 
 async fn create_and_insert_items(
-    manager: &mut CacheManager,
+    manager: &CacheManager,
     table_name: &str,
 ) -> Result<(), sled::Error> {
+    let codec = manager.table_codec(table_name).await?;
     for i in 1..=10000 {
         let item = HashMap::from([
             ("session_id".to_string(), i.to_string()),
@@ -24,39 +26,20 @@ async fn create_and_insert_items(
 
         let session_key = item.get("session_id").unwrap().as_str();
 
-        match serde_json::to_vec(&item) {
+        match codec.encode(&item) {
             Ok(session_value) => {
                 manager
                     .insert_async(table_name, session_key, session_value)
                     .await?;
             }
             Err(err) => {
-                eprintln!("Error serializing item to JSON: {}", err);
+                eprintln!("Error serializing item: {}", err);
             }
         }
     }
     Ok(())
 }
 
-async fn read_all_items(
-    manager: &mut CacheManager,
-    table_name: &str,
-) -> Result<Vec<(String, Vec<u8>)>, sled::Error> {
-    let tree = manager.get_or_create_table(table_name)?;
-    let items: Vec<(Vec<u8>, Vec<u8>)> = tree
-        .iter()
-        .filter_map(|item| item.ok())
-        .collect::<Vec<(IVec, IVec)>>()
-        .into_iter()
-        .map(|(key, value)| (key.to_vec(), value.to_vec()))
-        .collect();
-    let items: Vec<(String, Vec<u8>)> = items
-        .into_iter()
-        .map(|(key, value)| (String::from_utf8(key).unwrap(), value))
-        .collect();
-    Ok(items)
-}
-
 // Function to convert any struct that implements Serialize to HashMap<String, String>
 fn to_hashmap<T: Serialize>(item: &T) -> HashMap<String, String> {
     // Serialize the struct into a JSON value (which is a serde_json::Value)
@@ -74,33 +57,28 @@ fn to_hashmap<T: Serialize>(item: &T) -> HashMap<String, String> {
 }
 
 async fn insert_messages(
-    manager: &mut CacheManager,
+    manager: &CacheManager,
     table_name: &str,
-    messages: &Vec<HashMap<String, String>>,
+    messages: &[HashMap<String, String>],
 ) -> Result<(), sled::Error> {
+    let codec = manager.table_codec(table_name).await?;
+    let mut items = Vec::with_capacity(messages.len());
     for item in messages.iter() {
         let item_key = item.get("session_id").unwrap().as_str();
 
-        match serde_json::to_vec(&item) {
-            Ok(item_value) => {
-                manager
-                    .insert_async(table_name, item_key, item_value)
-                    .await?;
-            }
+        match codec.encode(&item) {
+            Ok(item_value) => items.push((item_key, item_value)),
             Err(err) => {
-                eprintln!("Error serializing item to JSON: {}", err);
+                eprintln!("Error serializing item: {}", err);
             }
         }
     }
-    Ok(())
+    manager.insert_many(table_name, items).await
 }
 
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
 async fn process_messages(
     batch: SyslogMessageBatch, // Passed by value
-    manager: &mut CacheManager,
+    manager: &CacheManager,
 ) -> Result<(), sled::Error> {
     use std::sync::Arc;
     use tokio::sync::Mutex;
@@ -109,7 +87,7 @@ async fn process_messages(
     let buffer: Vec<(DateTime<Utc>, SyslogMessage)> = batch
         .get_buffer()
         .iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
+        .map(|(k, v)| (*k, v.clone()))
         .collect();
 
     // Shared vectors to store 'open' and 'close' messages
@@ -143,9 +121,13 @@ async fn process_messages(
         }
     }
 
-    // Insert messages into the database
-    insert_messages(manager, "open", &*open_messages.lock().await).await?;
-    insert_messages(manager, "close", &*close_messages.lock().await).await?;
+    // Insert messages into the database, one batch per table
+    let open_messages = open_messages.lock().await;
+    let close_messages = close_messages.lock().await;
+    tokio::try_join!(
+        insert_messages(manager, "open", &open_messages),
+        insert_messages(manager, "close", &close_messages),
+    )?;
 
     Ok(())
 }
@@ -158,29 +140,58 @@ fn print_type<T>(value: T) {
     println!("The type of value is: {}", type_name::<T>());
 }
 */
+const SNAPSHOT_USAGE: &str = "Usage: test_v04 snapshot export <file.jsonl|file.arrow> [table...]
+       test_v04 snapshot import <file.jsonl|file.arrow>";
+
+/// `test_v04 snapshot export|import ...`: copies `my_cache` to or from a portable file.
+async fn run_snapshot(manager: &CacheManager, args: &[String]) -> Result<(), SnapshotError> {
+    let usage = || SnapshotError::Format(SNAPSHOT_USAGE.to_string());
+    let command = args.first().ok_or_else(usage)?;
+    let path = args.get(1).ok_or_else(usage)?;
+    let format = SnapshotFormat::from_path(path).ok_or_else(usage)?;
+
+    match command.as_str() {
+        "export" => {
+            let tables: Vec<&str> = args[2..].iter().map(String::as_str).collect();
+            let tables = (!tables.is_empty()).then_some(tables.as_slice());
+            let summary = manager.export_snapshot(path, format, tables).await?;
+            println!(
+                "Exported {} entries from {} trees to {}",
+                summary.entries,
+                summary.trees.len(),
+                path
+            );
+        }
+        "import" => {
+            let summary = manager.import_snapshot(path, format).await?;
+            println!(
+                "Imported {} entries into {} trees from {} (taken at {} ms)",
+                summary.entries,
+                summary.trees.len(),
+                path,
+                summary.created_at
+            );
+        }
+        _ => return Err(usage()),
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), sled::Error> {
-    let start = Instant::now();
-    /*
-        let mut manager = CacheManager::new("my_cache")?;
-        create_and_insert_items(&mut manager, "sessions").await?;
-
-        let items = read_all_items(&mut manager, "sessions").await?;
-        for (key, value) in items {
-            let item: HashMap<String, String> = match serde_json::from_slice(&value) {
-                Ok(item) => item,
-                Err(err) => {
-                    eprintln!("Error deserializing item from JSON: {}", err);
-                    return Ok(());
-                }
-            };
-            println!("Session {}: {:?}", key, item);
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("snapshot") {
+        let manager = CacheManager::new("my_cache")?;
+        if let Err(e) = run_snapshot(&manager, &args[2..]).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    */
+        return Ok(());
+    }
 
     /*
         let mut manager = CacheManager::new("my_cache")?;
-        create_and_insert_items(&mut manager, "sessions").await?;
+        create_and_insert_items(&manager, "sessions").await?;
 
         let mut batch = SyslogMessageBatch::new();
 
@@ -206,11 +217,11 @@ async fn main() -> Result<(), sled::Error> {
         println!("Size of close_messages: {}", close_messages.len());
 
         let table_name = "close";
-        insert_messages(&mut manager, table_name, &close_messages).await?;
+        insert_messages(&manager, table_name, &close_messages).await?;
 
 
         let table_name = "open";
-        insert_messages(&mut manager, table_name, &open_messages).await?;
+        insert_messages(&manager, table_name, &open_messages).await?;
 
 
         let duration = start.elapsed();
@@ -219,15 +230,47 @@ async fn main() -> Result<(), sled::Error> {
     */
     let start = Instant::now();
 
-    let mut manager = CacheManager::new("my_cache")?;
-    create_and_insert_items(&mut manager, "sessions").await?;
+    let manager = CacheManager::new("my_cache")?;
+    let policy = TablePolicy::new()
+        .with_ttl(SESSION_TTL)
+        .with_max_entries(MAX_SESSIONS);
+    for table_name in ["sessions", "open", "close"] {
+        manager.set_table_policy(table_name, policy).await?;
+    }
+    let sweeper = manager.spawn_sweeper(SWEEP_INTERVAL);
+
+    create_and_insert_items(&manager, "sessions").await?;
 
-    let mut batch = SyslogMessageBatch::new();
+    let batch = SyslogMessageBatch::new();
 
-    process_messages(batch, &mut manager).await?;
+    process_messages(batch, &manager).await?;
 
     let duration = start.elapsed();
     println!("Time taken to process messages: {:?}", duration);
 
+    for table_name in manager.table_names() {
+        let stats = manager.stats(&table_name)?;
+        println!(
+            "Table {}: {} entries, {} bytes",
+            stats.name, stats.entries, stats.bytes
+        );
+    }
+
+    for table_name in ["sessions", "open", "close"] {
+        if let Some(metrics) = manager.metrics(table_name) {
+            println!(
+                "{}: {} entries, {} bytes, {} hits, {} misses, {} expired, {} evicted",
+                table_name,
+                metrics.entries,
+                metrics.bytes,
+                metrics.hits,
+                metrics.misses,
+                metrics.expirations,
+                metrics.evictions
+            );
+        }
+    }
+    sweeper.abort();
+
     Ok(())
 }
@@ -5,7 +5,6 @@ use datafusion::arrow::{
     record_batch::RecordBatch,
 };
 use datafusion::dataframe::DataFrameWriteOptions;
-use datafusion::datasource::memory::MemTable;
 use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::SessionContext;
 use datafusion::prelude::*;
@@ -30,6 +29,7 @@ pub struct SyslogMessage {
     pub start_ts: String,
     pub end_ts: String,
     pub duration: String,
+    pub bytes: String,
     pub msg_type: String,
 }
 
@@ -55,6 +55,8 @@ impl SyslogMessageBatch {
             let random_seconds = rng.random_range(0..120);
             let start_ts = start_interval + Duration::seconds(random_seconds as i64);
             let duration = rng.random_range(0..120);
+            // Traffic of the whole session, reported when it closes
+            let bytes = duration * rng.random_range(1_000..125_000);
             let end_ts = start_ts + Duration::seconds(duration as i64);
 
             let session_id = format!("{}", rng.random_range(10000000..99999999));
@@ -76,6 +78,7 @@ impl SyslogMessageBatch {
                 start_ts: start_ts_str,
                 end_ts: Utc::now().to_rfc3339(),
                 duration: "0".to_string(),
+                bytes: "0".to_string(),
                 msg_type: "open".to_string(),
             };
 
@@ -88,6 +91,7 @@ impl SyslogMessageBatch {
                 start_ts: Utc::now().to_rfc3339(),
                 end_ts: end_ts_str,
                 duration: duration_str,
+                bytes: format!("{}", bytes),
                 msg_type: "close".to_string(),
             };
 
@@ -161,6 +165,7 @@ impl SyslogMessageBatch {
 }
 
 pub async fn generate_file(
+    ctx: &SessionContext,
     input: &Vec<SyslogMessage>,
     schema: Arc<Schema>,
     output_path: &str,
@@ -213,6 +218,12 @@ pub async fn generate_file(
             .map(|msg| msg.duration.clone())
             .collect::<Vec<String>>(),
     );
+    let bytes = StringArray::from(
+        input
+            .iter()
+            .map(|msg| msg.bytes.clone())
+            .collect::<Vec<String>>(),
+    );
     let msg_types = StringArray::from(
         input
             .iter()
@@ -232,22 +243,17 @@ pub async fn generate_file(
             Arc::new(start_ts),
             Arc::new(end_ts),
             Arc::new(durations),
+            Arc::new(bytes),
             Arc::new(msg_types),
         ],
     )?;
 
-    // Step 3: Create a MemTable
-    let table = MemTable::try_new(schema.clone(), vec![vec![record_batch]])?;
-
-    // Step 4: Create a SessionContext and register the table
-    let ctx = SessionContext::new();
-    ctx.register_table("my_table", Arc::new(table))?;
-
-    // Step 5: Query the DataFrame
-    let df = ctx.sql("SELECT * FROM my_table").await?;
+    // Step 3: Wrap the batch in a DataFrame of the shared context; nothing is
+    // registered, so the context does not grow with every file
+    let df = ctx.read_batch(record_batch)?;
 //    df.clone().show().await?;
 
-    // Step 6: Write to a Parquet file
+    // Step 4: Write to a Parquet file
     df.write_parquet(output_path, DataFrameWriteOptions::new(), None)
         .await?;
 
@@ -285,9 +291,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Field::new("start_ts", DataType::Utf8, false),
         Field::new("end_ts", DataType::Utf8, false),
         Field::new("duration", DataType::Utf8, false),
+        Field::new("bytes", DataType::Utf8, false),
         Field::new("msg_type", DataType::Utf8, false),
     ]));
 
+    // One context for every file written by this run
+    let ctx = SessionContext::new();
+
     // Create SyslogMessageBatch and load data
     let mut batch = SyslogMessageBatch::new();
     batch.load(BATCH_SIZE).await.unwrap();
@@ -298,11 +308,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         // Generate filenames using the helper function
         let open_filename = generate_parquet_filename("OPEN", "minidl/RAW");
-        generate_file(&open_out, schema.clone(), &open_filename).await?;
+        generate_file(&ctx, &open_out, schema.clone(), &open_filename).await?;
         info!("Generated open {}", open_filename);
 
         let close_filename = generate_parquet_filename("CLOSE", "minidl/RAW");
-        generate_file(&close_out, schema.clone(), &close_filename).await?;
+        generate_file(&ctx, &close_out, schema.clone(), &close_filename).await?;
         info!("Generated close {}", close_filename);
     }
 
@@ -0,0 +1,291 @@
+use datafusion::arrow::datatypes::DataType;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::prelude::*;
+use udf::write::dir_location;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Parquet,
+    Delta,
+    Csv,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Parquet => write!(f, "parquet"),
+            Format::Delta => write!(f, "delta"),
+            Format::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// A table found under the data lake root.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub format: Format,
+    /// A directory, a single file or a glob such as `RAW/OPEN*.parquet`.
+    pub location: String,
+    /// Hive-style `key=value` directory levels, read as Utf8 columns.
+    pub partitions: Vec<String>,
+}
+
+/// Finds every table under `root`:
+///
+/// * a directory holding `_delta_log` is a Delta table;
+/// * a directory of Parquet files, or of `key=value` partition directories,
+///   is one Parquet table. Files named `<PREFIX><digits>.parquet`, like the
+///   `OPEN*`/`CLOSE*` drops in `RAW`, also get a table per prefix;
+/// * the CSV files of a directory are one table, except at the root where
+///   each file is its own table.
+///
+/// Tables are named after their path relative to `root`, lower case, with
+/// `_` between levels: `RAW/OPEN*.parquet` is `raw_open`.
+///
+/// Directories that cannot be read as a table are returned with the reason
+/// next to the tables that can.
+pub fn discover(root: &Path) -> Result<(Vec<Source>, Vec<(String, DataFusionError)>)> {
+    let mut sources = Vec::new();
+    let mut skipped = Vec::new();
+    visit(root, root, &mut sources, &mut skipped)?;
+    sources.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((sources, skipped))
+}
+
+fn visit(
+    root: &Path,
+    dir: &Path,
+    sources: &mut Vec<Source>,
+    skipped: &mut Vec<(String, DataFusionError)>,
+) -> Result<()> {
+    let name = table_name(root, dir);
+    if dir.join("_delta_log").is_dir() {
+        sources.push(Source {
+            name,
+            format: Format::Delta,
+            location: location(dir),
+            partitions: Vec::new(),
+        });
+        return Ok(());
+    }
+
+    let mut parquet = Vec::new();
+    let mut csv = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            subdirs.push(path);
+            continue;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => parquet.push(path),
+            Some("csv") => csv.push(path),
+            _ => {}
+        }
+    }
+    subdirs.sort();
+
+    let partitions = partition_columns(&subdirs).unwrap_or_else(|e| {
+        skipped.push((name.clone(), e));
+        Vec::new()
+    });
+    if !parquet.is_empty() || !partitions.is_empty() {
+        sources.push(Source {
+            name: name.clone(),
+            format: Format::Parquet,
+            location: dir_location(dir),
+            partitions,
+        });
+        for prefix in file_prefixes(&parquet) {
+            sources.push(Source {
+                name: join_name(&name, &prefix),
+                format: Format::Parquet,
+                location: format!("{}{}*.parquet", dir_location(dir), prefix),
+                partitions: Vec::new(),
+            });
+        }
+    }
+
+    if dir == root {
+        for file in &csv {
+            let stem = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("");
+            sources.push(Source {
+                name: sanitize(stem),
+                format: Format::Csv,
+                location: location(file),
+                partitions: Vec::new(),
+            });
+        }
+    } else if !csv.is_empty() {
+        // Parquet and CSV side by side: the Parquet table keeps the plain name.
+        let csv_name = if parquet.is_empty() {
+            name
+        } else {
+            join_name(&name, "csv")
+        };
+        sources.push(Source {
+            name: csv_name,
+            format: Format::Csv,
+            location: dir_location(dir),
+            partitions: Vec::new(),
+        });
+    }
+
+    for subdir in subdirs {
+        if !is_partition(&subdir) {
+            visit(root, &subdir, sources, skipped)?;
+        }
+    }
+    Ok(())
+}
+
+/// Registers every source, reporting the ones that fail instead of giving
+/// up, so one broken table does not hide the rest of the lake.
+pub async fn register_all(
+    ctx: &SessionContext,
+    sources: &[Source],
+) -> Vec<(String, DataFusionError)> {
+    let mut failures = Vec::new();
+    for source in sources {
+        if let Err(e) = register(ctx, source).await {
+            failures.push((source.name.clone(), e));
+        }
+    }
+    failures
+}
+
+pub async fn register(ctx: &SessionContext, source: &Source) -> Result<()> {
+    let name = source.name.as_str();
+    // Rescans replace what an earlier scan registered.
+    ctx.deregister_table(name)?;
+    match source.format {
+        Format::Parquet => {
+            let partitions = source
+                .partitions
+                .iter()
+                .map(|column| (column.clone(), DataType::Utf8))
+                .collect();
+            let options = ParquetReadOptions::default().table_partition_cols(partitions);
+            ctx.register_parquet(name, &source.location, options).await
+        }
+        Format::Csv => {
+            ctx.register_csv(name, &source.location, CsvReadOptions::new())
+                .await
+        }
+        Format::Delta => {
+            let table = deltalake::open_table(&source.location)
+                .await
+                .map_err(|e| DataFusionError::External(Box::new(e)))?;
+            ctx.register_table(name, Arc::new(table))?;
+            Ok(())
+        }
+    }
+}
+
+/// Column names of nested `key=value` directories, outermost first. Every
+/// partition directory of a level has to use the same key.
+fn partition_columns(subdirs: &[PathBuf]) -> Result<Vec<String>> {
+    let keys: BTreeMap<String, &PathBuf> = subdirs
+        .iter()
+        .filter_map(|dir| partition_key(dir).map(|key| (key, dir)))
+        .collect();
+    match keys.len() {
+        0 => Ok(Vec::new()),
+        1 => {
+            let (key, dir) = keys.into_iter().next().unwrap();
+            let subdirs: Vec<PathBuf> = fs::read_dir(dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_dir())
+                .collect();
+            let mut columns = vec![key];
+            columns.extend(partition_columns(&subdirs)?);
+            Ok(columns)
+        }
+        _ => Err(DataFusionError::Plan(format!(
+            "{}: partition directories use different keys: {}",
+            subdirs[0].parent().map(location).unwrap_or_default(),
+            keys.into_keys().collect::<Vec<_>>().join(", ")
+        ))),
+    }
+}
+
+fn partition_key(dir: &Path) -> Option<String> {
+    let name = dir.file_name()?.to_str()?;
+    let (key, _) = name.split_once('=')?;
+    Some(key.to_string())
+}
+
+fn is_partition(dir: &Path) -> bool {
+    partition_key(dir).is_some()
+}
+
+/// Distinct `PREFIX` of files named `PREFIX<digits>.parquet`, only when there
+/// is more than one, since a single prefix would just repeat the directory.
+fn file_prefixes(files: &[PathBuf]) -> Vec<String> {
+    let mut prefixes: Vec<String> = files
+        .iter()
+        .filter_map(|file| {
+            let stem = file.file_stem()?.to_str()?;
+            let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+            let numbered = prefix.len() < stem.len();
+            (numbered && !prefix.is_empty() && prefix.chars().all(|c| c.is_ascii_alphabetic()))
+                .then(|| prefix.to_string())
+        })
+        .collect();
+    prefixes.sort();
+    prefixes.dedup();
+    if prefixes.len() > 1 {
+        prefixes
+    } else {
+        Vec::new()
+    }
+}
+
+fn table_name(root: &Path, dir: &Path) -> String {
+    let relative = dir.strip_prefix(root).unwrap_or(dir);
+    let parts: Vec<String> = relative
+        .components()
+        .filter_map(|part| part.as_os_str().to_str())
+        .map(sanitize)
+        .collect();
+    if parts.is_empty() {
+        let root_name = root
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("lake");
+        sanitize(root_name)
+    } else {
+        parts.join("_")
+    }
+}
+
+fn join_name(name: &str, suffix: &str) -> String {
+    format!("{}_{}", name, sanitize(suffix))
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn location(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
@@ -1,19 +1,55 @@
-use datafusion::prelude::*;
-use datafusion::error::Result;
-use tokio;
+mod catalog;
+mod shell;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Create a session context
-    let ctx = SessionContext::new();
+use shell::{Flow, Shell};
+
+use std::path::PathBuf;
 
-    // Read the Parquet file asynchronously
-    let parquet_file = "../minidl/RAW/CLOSE20250128195648.parquet";
-    let df = ctx.read_parquet(parquet_file, ParquetReadOptions::new()).await?;
+/// Used when neither `--root` nor `MINIDL` names the data lake.
+const DEFAULT_ROOT: &str = "../minidl";
 
-    // Show the DataFrame (useful for debugging)
-    df.show().await?;
+const USAGE: &str = "Usage: test_v08 [--root <dir>] [-c <statement>]...
 
+Opens an SQL shell over every Parquet, Delta and CSV table under the data
+lake directory (default: $MINIDL or ../minidl). With -c, runs the statements
+or backslash commands in order and exits, with status 1 if any of them failed.";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut root = std::env::var("MINIDL").unwrap_or_else(|_| DEFAULT_ROOT.to_string());
+    let mut statements = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = args.next().ok_or(USAGE)?,
+            "-c" => statements.push(args.next().ok_or(USAGE)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(format!("unknown argument {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let mut shell = Shell::open(&PathBuf::from(root)).await?;
+    if statements.is_empty() {
+        shell.run().await?;
+    } else {
+        let mut failed = false;
+        for statement in &statements {
+            match shell.try_execute(statement).await {
+                Ok(Flow::Quit) => break,
+                Ok(Flow::Continue) => {}
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+    }
     Ok(())
 }
-
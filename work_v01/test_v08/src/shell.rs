@@ -0,0 +1,290 @@
+use crate::catalog::{self, Source};
+
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::prelude::*;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use udf::register_udfs;
+use udf::write::written_rows;
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const PROMPT: &str = "minidl> ";
+const CONTINUATION: &str = "     -> ";
+
+const HELP: &str = "SQL statements end with `;` and may span lines. EXPLAIN and
+EXPLAIN ANALYZE show the plan of a query. The network UDFs (ip_to_int,
+int_to_ip, cidr_contains, mac_oui, mac_normalize, msisdn_country,
+imsi_mcc_mnc, luhn_valid, port_service) are available in every query.
+
+  \\d                      list tables
+  \\d <table>              describe a table
+  \\timing [on|off]        toggle printing query times
+  \\export <file> [sql]    write the result of sql, or of the last query, to
+                          <file>.csv, <file>.parquet or <file>.json
+  \\rescan                 pick up tables added to or removed from the lake
+  \\?                      this help
+  \\q                      quit";
+
+/// Whether the shell keeps reading after a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// An interactive SQL session over every table of a data lake directory.
+pub struct Shell {
+    ctx: SessionContext,
+    root: PathBuf,
+    sources: Vec<Source>,
+    timing: bool,
+    last_query: Option<String>,
+}
+
+impl Shell {
+    pub async fn open(root: &Path) -> Result<Self> {
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        let mut shell = Shell {
+            ctx,
+            root: root.to_path_buf(),
+            sources: Vec::new(),
+            timing: false,
+            last_query: None,
+        };
+        shell.rescan().await?;
+        Ok(shell)
+    }
+
+    pub async fn rescan(&mut self) -> Result<()> {
+        let (sources, mut skipped) = catalog::discover(&self.root)?;
+        // Tables removed from the lake since the last scan go away too.
+        for old in &self.sources {
+            if !sources.iter().any(|source| source.name == old.name) {
+                self.ctx.deregister_table(old.name.as_str())?;
+            }
+        }
+        skipped.extend(catalog::register_all(&self.ctx, &sources).await);
+        for (name, e) in skipped {
+            eprintln!("Skipping table {}: {}", name, e);
+        }
+        println!(
+            "{} tables under {}",
+            sources.len(),
+            self.root.to_string_lossy()
+        );
+        self.sources = sources;
+        Ok(())
+    }
+
+    /// Reads statements until `\q` or end of input. Errors are printed and the
+    /// session goes on.
+    pub async fn run(&mut self) -> Result<()> {
+        let mut editor =
+            DefaultEditor::new().map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION
+            };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C drops the statement being typed.
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => return Ok(()),
+                Err(e) => return Err(DataFusionError::External(Box::new(e))),
+            };
+
+            let trimmed = line.trim();
+            if buffer.is_empty() && trimmed.is_empty() {
+                continue;
+            }
+            if buffer.is_empty() && trimmed.starts_with('\\') {
+                let _ = editor.add_history_entry(trimmed);
+                if self.execute(trimmed).await == Flow::Quit {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if trimmed.ends_with(';') {
+                let statement = std::mem::take(&mut buffer);
+                let _ = editor.add_history_entry(statement.trim());
+                self.execute(&statement).await;
+            }
+        }
+    }
+
+    /// Runs one backslash command or SQL statement, printing its result or
+    /// error.
+    pub async fn execute(&mut self, input: &str) -> Flow {
+        match self.try_execute(input).await {
+            Ok(flow) => flow,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                Flow::Continue
+            }
+        }
+    }
+
+    /// Like `execute`, but hands the error back instead of printing it.
+    pub async fn try_execute(&mut self, input: &str) -> Result<Flow> {
+        let input = input.trim();
+        match input.strip_prefix('\\') {
+            Some(command) => self.command(command).await,
+            None => self.query(input).await,
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Flow> {
+        let (name, args) = match command.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (command, ""),
+        };
+        match name {
+            "q" | "quit" => return Ok(Flow::Quit),
+            "?" | "h" | "help" => println!("{}", HELP),
+            "d" if args.is_empty() => self.list_tables(),
+            "d" => self.describe(args).await?,
+            "timing" => {
+                self.timing = match args {
+                    "" => !self.timing,
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(usage("\\timing [on|off]")),
+                };
+                println!("Timing is {}", if self.timing { "on" } else { "off" });
+            }
+            "export" => {
+                let (path, sql) = match args.split_once(char::is_whitespace) {
+                    Some((path, sql)) => (path, Some(sql.trim())),
+                    None => (args, None),
+                };
+                if path.is_empty() {
+                    return Err(usage("\\export <file> [sql]"));
+                }
+                self.export(path, sql).await?;
+            }
+            "rescan" => self.rescan().await?,
+            _ => {
+                return Err(DataFusionError::Plan(format!(
+                    "unknown command \\{}, try \\?",
+                    name
+                )))
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn query(&mut self, sql: &str) -> Result<Flow> {
+        let sql = sql.trim_end_matches(';').trim();
+        if sql.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        let started = Instant::now();
+        let batches = self.ctx.sql(sql).await?.collect().await?;
+        let elapsed = started.elapsed();
+
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        if !batches.is_empty() {
+            println!("{}", pretty_format_batches(&batches)?);
+        }
+        println!("({} rows)", rows);
+        if self.timing {
+            println!("Time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        self.last_query = Some(sql.to_string());
+        Ok(Flow::Continue)
+    }
+
+    fn list_tables(&self) {
+        let width = self
+            .sources
+            .iter()
+            .map(|source| source.name.len())
+            .max()
+            .unwrap_or(0);
+        for source in &self.sources {
+            let partitions = if source.partitions.is_empty() {
+                String::new()
+            } else {
+                format!(" partitioned by {}", source.partitions.join(", "))
+            };
+            println!(
+                "{:width$}  {:7}  {}{}",
+                source.name,
+                source.format.to_string(),
+                source.location,
+                partitions,
+                width = width
+            );
+        }
+    }
+
+    async fn describe(&self, table: &str) -> Result<()> {
+        let df = self.ctx.table(table).await?;
+        let fields = df.schema().fields();
+        let width = fields
+            .iter()
+            .map(|field| field.name().len())
+            .max()
+            .unwrap_or(0);
+        for field in fields {
+            println!(
+                "{:width$}  {}{}",
+                field.name(),
+                field.data_type(),
+                if field.is_nullable() { "" } else { " NOT NULL" },
+                width = width
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes a query result as CSV, Parquet or JSON lines, chosen by the
+    /// extension of `path`.
+    async fn export(&mut self, path: &str, sql: Option<&str>) -> Result<()> {
+        let sql = match sql {
+            Some(sql) => sql.trim_end_matches(';').trim().to_string(),
+            None => self
+                .last_query
+                .clone()
+                .ok_or_else(|| usage("\\export <file> <sql>, no query was run yet"))?,
+        };
+        let df = self.ctx.sql(&sql).await?;
+        let options = DataFrameWriteOptions::new();
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        let written = match extension.as_deref() {
+            Some("csv") => df.write_csv(path, options, None).await?,
+            Some("parquet") => df.write_parquet(path, options, None).await?,
+            Some("json") | Some("ndjson") => df.write_json(path, options, None).await?,
+            _ => {
+                return Err(DataFusionError::Plan(format!(
+                    "{}: export to .csv, .parquet or .json",
+                    path
+                )))
+            }
+        };
+        println!("Wrote {} rows to {}", written_rows(&written)?, path);
+        Ok(())
+    }
+}
+
+fn usage(usage: &str) -> DataFusionError {
+    DataFusionError::Plan(format!("usage: {}", usage))
+}
@@ -0,0 +1,297 @@
+use crate::ingest::{events_schema, KINDS};
+
+use datafusion::arrow::array::{Array, AsArray, TimestampNanosecondArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit, TimestampNanosecondType};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::error::Result;
+use datafusion::prelude::*;
+use log::info;
+use udf::register_udfs;
+use udf::write::{dir_location, written_rows};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Window sizes: partition label, SQL interval and length in nanoseconds.
+pub const WINDOWS: [(&str, &str, i64); 3] = [
+    ("1m", "1 minute", 60_000_000_000),
+    ("5m", "5 minutes", 300_000_000_000),
+    ("1h", "1 hour", 3_600_000_000_000),
+];
+
+/// Rows kept per window by the top talker and top port rankings.
+pub const TOP_N: usize = 10;
+
+/// Columns the outputs are partitioned by, as
+/// `window_size=1m/window_date=2025-01-28`.
+const PARTITION_BY: [&str; 2] = ["window_size", "window_date"];
+
+const ORIGIN: &str = "TIMESTAMP '1970-01-01T00:00:00'";
+
+/// One row per session, joining the OPEN and CLOSE records on `session_id`.
+/// An OPEN record's `end_ts` and a CLOSE record's `start_ts` are the time
+/// the record was written, so the start comes from OPEN and the end from
+/// CLOSE. Sessions whose OPEN is not in the data have no `opened_at`, and
+/// sessions still open have no `closed_at`.
+const SESSIONS_SQL: &str = "
+SELECT COALESCE(o.session_id, c.session_id) AS session_id,
+       COALESCE(o.source_ip_address, c.source_ip_address) AS source_ip,
+       COALESCE(o.dest_port, c.dest_port) AS dest_port,
+       o.start_ts AS opened_at,
+       c.end_ts AS closed_at,
+       CAST(c.duration AS DOUBLE) AS duration,
+       c.bytes
+FROM open_events o FULL OUTER JOIN close_events c ON o.session_id = c.session_id";
+
+const METRICS_SQL: &str = "
+WITH opened AS (
+    SELECT date_bin(INTERVAL '{interval}', opened_at, {origin}) AS window_start,
+           count(*) AS new_sessions
+    FROM sessions
+    WHERE opened_at IS NOT NULL
+    GROUP BY 1
+),
+closed AS (
+    SELECT date_bin(INTERVAL '{interval}', closed_at, {origin}) AS window_start,
+           count(*) AS closed_sessions,
+           approx_percentile_cont(duration, 0.5) AS duration_p50,
+           approx_percentile_cont(duration, 0.9) AS duration_p90,
+           approx_percentile_cont(duration, 0.99) AS duration_p99,
+           max(duration) AS duration_max,
+           sum(bytes) AS bytes
+    FROM sessions
+    WHERE closed_at IS NOT NULL
+    GROUP BY 1
+),
+concurrent AS (
+    SELECT g.window_start, g.window_end, count(s.session_id) AS concurrent_sessions
+    FROM grid g
+    LEFT JOIN sessions s
+      ON s.opened_at < g.window_end
+     AND (s.closed_at IS NULL OR s.closed_at >= g.window_start)
+    GROUP BY g.window_start, g.window_end
+)
+SELECT '{label}' AS window_size,
+       to_char(c.window_start, '%Y-%m-%d') AS window_date,
+       c.window_start,
+       c.window_end,
+       COALESCE(o.new_sessions, 0) AS new_sessions,
+       COALESCE(cl.closed_sessions, 0) AS closed_sessions,
+       c.concurrent_sessions,
+       cl.duration_p50,
+       cl.duration_p90,
+       cl.duration_p99,
+       cl.duration_max,
+       COALESCE(cl.bytes, 0) AS bytes
+FROM concurrent c
+LEFT JOIN opened o ON o.window_start = c.window_start
+LEFT JOIN closed cl ON cl.window_start = c.window_start
+ORDER BY c.window_start";
+
+/// Top `{key}` values by bytes, then sessions, among sessions opened in each
+/// window. `{extra}` adds columns computed from the key.
+const TOP_SQL: &str = "
+SELECT '{label}' AS window_size,
+       to_char(window_start, '%Y-%m-%d') AS window_date,
+       window_start,
+       ranking,
+       {key}{extra},
+       sessions,
+       bytes
+FROM (
+    SELECT window_start, {key}, sessions, bytes,
+           row_number() OVER (
+               PARTITION BY window_start
+               ORDER BY bytes DESC, sessions DESC, {key}
+           ) AS ranking
+    FROM (
+        SELECT date_bin(INTERVAL '{interval}', opened_at, {origin}) AS window_start,
+               {key},
+               count(*) AS sessions,
+               COALESCE(sum(bytes), 0) AS bytes
+        FROM sessions
+        WHERE opened_at IS NOT NULL AND {key} IS NOT NULL
+        GROUP BY 1, 2
+    )
+)
+WHERE ranking <= {top}
+ORDER BY window_start, ranking";
+
+/// Per-window session metrics over the OPEN/CLOSE syslog events ingested
+/// from a data lake's RAW drops, written back to the lake as partitioned
+/// Parquet:
+///
+/// * `CURATED/session_metrics`: new, closed and concurrent sessions, duration
+///   percentiles and bytes;
+/// * `CURATED/top_talkers`: source IPs with the most traffic;
+/// * `CURATED/top_ports`: destination ports with the most traffic, with
+///   their service name.
+pub struct SessionAnalytics {
+    ctx: SessionContext,
+}
+
+impl SessionAnalytics {
+    /// Registers the `kind=open` and `kind=close` events written by
+    /// `ingest` under `events` and the `sessions` view joining them.
+    pub async fn open(events: &Path) -> Result<Self> {
+        let ctx = SessionContext::new();
+        register_udfs(&ctx);
+        let schema = events_schema();
+        for (_, kind) in KINDS {
+            let dir = events.join(format!("kind={}", kind));
+            std::fs::create_dir_all(&dir)?;
+            let dir = dir_location(&dir);
+            let options = ParquetReadOptions::default().schema(&schema);
+            ctx.register_parquet(format!("{}_events", kind).as_str(), &dir, options)
+                .await?;
+        }
+
+        let sessions = ctx.sql(SESSIONS_SQL).await?;
+        ctx.register_table("sessions", sessions.into_view())?;
+
+        Ok(SessionAnalytics { ctx })
+    }
+
+    /// Computes every window size and writes the three outputs under
+    /// `curated`, replacing what an earlier run wrote there. They are written
+    /// next to the old ones first and swapped in once all are complete, so a
+    /// failed run leaves the previous outputs in place. Without any sessions
+    /// the outputs are replaced by empty ones, so rows of pruned files go too.
+    pub async fn run(&self, curated: &Path) -> Result<()> {
+        let outputs = ["session_metrics", "top_talkers", "top_ports"];
+        for output in outputs {
+            let staging = staging_dir(curated, output);
+            remove_dir(&staging)?;
+            std::fs::create_dir_all(&staging)?;
+        }
+
+        if let Some((first, last)) = self.time_range().await? {
+            for (label, interval, nanos) in WINDOWS {
+                self.register_grid(first, last, nanos)?;
+
+                let metrics = METRICS_SQL
+                    .replace("{interval}", interval)
+                    .replace("{origin}", ORIGIN)
+                    .replace("{label}", label);
+                let talkers = top_sql(label, interval, "source_ip", "");
+                let ports = top_sql(
+                    label,
+                    interval,
+                    "dest_port",
+                    ", port_service(dest_port, 'tcp') AS service",
+                );
+
+                for (output, sql) in outputs.iter().zip([metrics, talkers, ports]) {
+                    let rows = self.write(&sql, &staging_dir(curated, output)).await?;
+                    info!("{} {}: {} rows", output, label, rows);
+                }
+            }
+        } else {
+            info!("No sessions to aggregate");
+        }
+
+        for output in outputs {
+            let dir = curated.join(output);
+            let old = curated.join(format!(".{}.old", output));
+            remove_dir(&old)?;
+            if dir.exists() {
+                std::fs::rename(&dir, &old)?;
+            }
+            std::fs::rename(staging_dir(curated, output), &dir)?;
+            remove_dir(&old)?;
+        }
+        Ok(())
+    }
+
+    /// First and last instant of any session, `None` when there are none.
+    async fn time_range(&self) -> Result<Option<(i64, i64)>> {
+        let batches = self
+            .ctx
+            .sql(
+                "SELECT min(COALESCE(opened_at, closed_at)), \
+                        max(COALESCE(closed_at, opened_at)) \
+                 FROM sessions",
+            )
+            .await?
+            .collect()
+            .await?;
+        let Some(batch) = batches.first().filter(|batch| batch.num_rows() > 0) else {
+            return Ok(None);
+        };
+        let first = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        let last = batch.column(1).as_primitive::<TimestampNanosecondType>();
+        if first.is_null(0) || last.is_null(0) {
+            return Ok(None);
+        }
+        Ok(Some((first.value(0), last.value(0))))
+    }
+
+    /// Registers `grid` with every window of `nanos` from the one holding
+    /// `first` to the one holding `last`, so windows without any open or
+    /// close event still report their concurrent sessions.
+    fn register_grid(&self, first: i64, last: i64, nanos: i64) -> Result<()> {
+        let starts: Vec<i64> = (first.div_euclid(nanos)..=last.div_euclid(nanos))
+            .map(|window| window * nanos)
+            .collect();
+        let ends: Vec<i64> = starts.iter().map(|start| start + nanos).collect();
+
+        let timestamp = DataType::Timestamp(TimeUnit::Nanosecond, None);
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("window_start", timestamp.clone(), false),
+            Field::new("window_end", timestamp, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampNanosecondArray::from(starts)),
+                Arc::new(TimestampNanosecondArray::from(ends)),
+            ],
+        )?;
+        self.ctx.deregister_table("grid")?;
+        self.ctx.register_batch("grid", batch)?;
+        Ok(())
+    }
+
+    /// Appends the rows of `sql` to the partitioned dataset `dir` and returns
+    /// how many were written.
+    async fn write(&self, sql: &str, dir: &Path) -> Result<u64> {
+        std::fs::create_dir_all(dir)?;
+        let dir = dir_location(dir);
+        let options = DataFrameWriteOptions::new().with_partition_by(
+            PARTITION_BY
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+        );
+        let written = self
+            .ctx
+            .sql(sql)
+            .await?
+            .write_parquet(&dir, options, None)
+            .await?;
+        written_rows(&written)
+    }
+}
+
+/// Where a run writes `output` before swapping it in.
+fn staging_dir(curated: &Path, output: &str) -> PathBuf {
+    curated.join(format!(".{}.tmp", output))
+}
+
+fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn top_sql(label: &str, interval: &str, key: &str, extra: &str) -> String {
+    TOP_SQL
+        .replace("{label}", label)
+        .replace("{interval}", interval)
+        .replace("{origin}", ORIGIN)
+        .replace("{extra}", extra)
+        .replace("{key}", key)
+        .replace("{top}", &TOP_N.to_string())
+}
@@ -0,0 +1,154 @@
+use crate::manifest::{Manifest, ManifestError};
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::error::Result;
+use datafusion::prelude::*;
+use log::{info, warn};
+use udf::write::{dir_location, written_rows};
+
+use std::path::{Path, PathBuf};
+
+/// RAW file name prefixes and the `kind` partition their events go to.
+pub const KINDS: [(&str, &str); 2] = [("OPEN", "open"), ("CLOSE", "close")];
+
+/// The RAW columns with their real types. Every column is nullable since
+/// older files lack `bytes`.
+pub fn events_schema() -> Schema {
+    let timestamp = DataType::Timestamp(TimeUnit::Nanosecond, None);
+    Schema::new(vec![
+        Field::new("session_id", DataType::Utf8, true),
+        Field::new("source_ip_address", DataType::Utf8, true),
+        Field::new("source_port", DataType::Int64, true),
+        Field::new("dest_ip_address", DataType::Utf8, true),
+        Field::new("dest_port", DataType::Int64, true),
+        Field::new("start_ts", timestamp.clone(), true),
+        Field::new("end_ts", timestamp, true),
+        Field::new("duration", DataType::Int64, true),
+        Field::new("bytes", DataType::Int64, true),
+        Field::new("msg_type", DataType::Utf8, true),
+    ])
+}
+
+const EVENTS_SQL: &str = "
+SELECT session_id,
+       source_ip_address,
+       TRY_CAST(source_port AS BIGINT) AS source_port,
+       dest_ip_address,
+       TRY_CAST(dest_port AS BIGINT) AS dest_port,
+       to_timestamp(start_ts) AS start_ts,
+       to_timestamp(end_ts) AS end_ts,
+       TRY_CAST(duration AS BIGINT) AS duration,
+       {bytes} AS bytes,
+       msg_type
+FROM raw_file";
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IngestSummary {
+    pub files: usize,
+    pub failed: usize,
+    pub rows: u64,
+}
+
+/// Copies every file the manifest still has to process from `raw` into
+/// `events/kind=<kind>/source_file=<name>/`, typed as `events_schema`.
+///
+/// A file's directory is emptied before it is written and the file is marked
+/// done only after the write, so a file processed again after a crash or a
+/// change replaces its earlier rows instead of adding to them. A file that
+/// fails is recorded with the error and its directory removed; the others
+/// go on.
+pub async fn ingest(
+    manifest: &Manifest,
+    raw: &Path,
+    events: &Path,
+) -> std::result::Result<IngestSummary, ManifestError> {
+    let ctx = SessionContext::new();
+    let mut summary = IngestSummary::default();
+
+    for entry in manifest.to_process()? {
+        let Some(output) = output_dir(events, &entry.file) else {
+            continue;
+        };
+        manifest.start(&entry.file)?;
+        match ingest_file(&ctx, &raw.join(&entry.file), &output).await {
+            Ok(rows) => {
+                manifest.finish(&entry.file, rows)?;
+                info!("Ingested {}: {} rows", entry.file, rows);
+                summary.files += 1;
+                summary.rows += rows;
+            }
+            Err(e) => {
+                if output.exists() {
+                    std::fs::remove_dir_all(&output)?;
+                }
+                manifest.fail(&entry.file, &e.to_string())?;
+                warn!("Failed to ingest {}: {}", entry.file, e);
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Deletes the events written from `files`, which are no longer in RAW, and
+/// then drops them from the manifest. Returns how many were pruned.
+pub fn prune(
+    manifest: &Manifest,
+    events: &Path,
+    files: &[String],
+) -> std::result::Result<usize, ManifestError> {
+    for file in files {
+        if let Some(output) = output_dir(events, file) {
+            if output.exists() {
+                std::fs::remove_dir_all(&output)?;
+            }
+        }
+        manifest.remove(file)?;
+        info!("Pruned {}, no longer in RAW", file);
+    }
+    Ok(files.len())
+}
+
+/// `events/kind=open/source_file=OPEN20250128195648`, or `None` for a file
+/// of no known kind.
+fn output_dir(events: &Path, file: &str) -> Option<PathBuf> {
+    let (_, kind) = KINDS.iter().find(|(prefix, _)| file.starts_with(prefix))?;
+    let stem = file.strip_suffix(".parquet").unwrap_or(file);
+    Some(
+        events
+            .join(format!("kind={}", kind))
+            .join(format!("source_file={}", stem)),
+    )
+}
+
+async fn ingest_file(ctx: &SessionContext, file: &Path, output: &Path) -> Result<u64> {
+    if output.exists() {
+        std::fs::remove_dir_all(output)?;
+    }
+    std::fs::create_dir_all(output)?;
+
+    let df = ctx
+        .read_parquet(
+            file.to_string_lossy().as_ref(),
+            ParquetReadOptions::default(),
+        )
+        .await?;
+    // Files written before the generator reported traffic have no bytes.
+    let bytes = if df.schema().has_column_with_unqualified_name("bytes") {
+        "TRY_CAST(bytes AS BIGINT)"
+    } else {
+        "CAST(NULL AS BIGINT)"
+    };
+    ctx.deregister_table("raw_file")?;
+    ctx.register_table("raw_file", df.into_view())?;
+
+    let dir = dir_location(output);
+    let written = ctx
+        .sql(&EVENTS_SQL.replace("{bytes}", bytes))
+        .await?
+        .write_parquet(&dir, DataFrameWriteOptions::new(), None)
+        .await?;
+    ctx.deregister_table("raw_file")?;
+    written_rows(&written)
+}
@@ -0,0 +1,114 @@
+mod analytics;
+mod ingest;
+mod manifest;
+
+use analytics::SessionAnalytics;
+use ingest::KINDS;
+use log::info;
+use manifest::Manifest;
+
+use std::path::{Path, PathBuf};
+
+/// Used when neither `--lake` nor `MINIDL` names the data lake.
+const DEFAULT_LAKE: &str = "../minidl";
+
+/// Where the manifest of ingested RAW files lives, inside the lake.
+const MANIFEST_DIR: &str = "_manifest";
+
+const USAGE: &str = "Usage: test_v10 [--lake <dir>] [command]
+
+Commands:
+  run                 ingest the OPEN/CLOSE files of <dir>/RAW not ingested yet
+                      into <dir>/CURATED/session_events, dropping the events of
+                      files no longer in RAW, then aggregate them per 1 minute,
+                      5 minute and 1 hour window into session_metrics,
+                      top_talkers and top_ports under <dir>/CURATED (default)
+  manifest            list the RAW files in the manifest with their status
+  retry <file>...     ingest failed files again on the next run
+
+The lake defaults to $MINIDL or ../minidl.";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let mut lake = std::env::var("MINIDL").unwrap_or_else(|_| DEFAULT_LAKE.to_string());
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lake" => lake = args.next().ok_or(USAGE)?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => command.push(arg),
+        }
+    }
+    let lake = PathBuf::from(lake);
+    let manifest = Manifest::open(lake.join(MANIFEST_DIR))?;
+
+    match command.first().map(String::as_str).unwrap_or("run") {
+        "run" => run(&lake, &manifest).await,
+        "manifest" => {
+            print_manifest(&manifest)?;
+            Ok(())
+        }
+        "retry" if command.len() > 1 => {
+            for file in &command[1..] {
+                manifest.retry(file)?;
+                println!("{} will be ingested on the next run", file);
+            }
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn run(lake: &Path, manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+    let raw = lake.join("RAW");
+    let curated = lake.join("CURATED");
+    let events = curated.join("session_events");
+
+    info!("Start");
+    let prefixes: Vec<&str> = KINDS.iter().map(|(prefix, _)| *prefix).collect();
+    let scan = manifest.scan(&raw, &prefixes)?;
+    info!(
+        "Manifest: {} new, {} changed, {} unchanged, {} removed files",
+        scan.new,
+        scan.changed,
+        scan.unchanged,
+        scan.removed.len()
+    );
+    ingest::prune(manifest, &events, &scan.removed)?;
+
+    let ingested = ingest::ingest(manifest, &raw, &events).await?;
+    info!(
+        "Ingested {} files ({} rows), {} failed",
+        ingested.files, ingested.rows, ingested.failed
+    );
+
+    let analytics = SessionAnalytics::open(&events).await?;
+    analytics.run(&curated).await?;
+    info!("End");
+    Ok(())
+}
+
+fn print_manifest(manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in manifest.entries()? {
+        let rows = entry.rows.map_or("-".to_string(), |rows| rows.to_string());
+        println!(
+            "{:<28} {:<10} {:>10} bytes {:>8} rows  attempts {}  sha256 {}",
+            entry.file,
+            entry.status.to_string(),
+            entry.size,
+            rows,
+            entry.attempts,
+            &entry.checksum[..12.min(entry.checksum.len())]
+        );
+        if let Some(error) = &entry.error {
+            println!("    {}", error);
+        }
+    }
+    Ok(())
+}
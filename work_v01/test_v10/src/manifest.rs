@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::{Db, Tree};
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILES_TREE: &str = "files";
+
+/// A failing file is tried this many times, then left for `test_v10 retry`.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(std::io::Error),
+    Sled(sled::Error),
+    Json(serde_json::Error),
+    UnknownFile(String),
+}
+
+impl std::error::Error for ManifestError {}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io(e) => write!(f, "Manifest IO error: {}", e),
+            ManifestError::Sled(e) => write!(f, "Manifest database error: {}", e),
+            ManifestError::Json(e) => write!(f, "Invalid manifest entry: {}", e),
+            ManifestError::UnknownFile(file) => write!(f, "{} is not in the manifest", file),
+        }
+    }
+}
+
+impl From<std::io::Error> for ManifestError {
+    fn from(e: std::io::Error) -> Self {
+        ManifestError::Io(e)
+    }
+}
+
+impl From<sled::Error> for ManifestError {
+    fn from(e: sled::Error) -> Self {
+        ManifestError::Sled(e)
+    }
+}
+
+impl From<serde_json::Error> for ManifestError {
+    fn from(e: serde_json::Error) -> Self {
+        ManifestError::Json(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// Found by a scan, or changed since it was processed.
+    Pending,
+    /// Being processed. Still set at startup means the last run stopped
+    /// halfway, so the file is processed again.
+    Processing,
+    Done,
+    Failed,
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileStatus::Pending => write!(f, "pending"),
+            FileStatus::Processing => write!(f, "processing"),
+            FileStatus::Done => write!(f, "done"),
+            FileStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// What the manifest knows about one file of the RAW directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// File name within the scanned directory.
+    pub file: String,
+    pub size: u64,
+    /// Modification time in milliseconds, used to skip the checksum of files
+    /// that did not change since the last scan.
+    pub modified: u64,
+    /// SHA-256 of the contents, hex encoded.
+    pub checksum: String,
+    /// Rows written by the last successful run.
+    pub rows: Option<u64>,
+    pub status: FileStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub updated_at: u64,
+}
+
+impl FileEntry {
+    /// Pending files, failed files with attempts left, and files a crashed
+    /// run left in processing.
+    pub fn needs_processing(&self) -> bool {
+        match self.status {
+            FileStatus::Pending | FileStatus::Processing => true,
+            FileStatus::Failed => self.attempts < MAX_ATTEMPTS,
+            FileStatus::Done => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScanSummary {
+    pub new: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Files still in the manifest that are no longer in the directory, for
+    /// the caller to clean up and `remove`.
+    pub removed: Vec<String>,
+}
+
+/// Ingested files of a directory, kept in sled so that a job picks up only
+/// new or changed files and knows which ones to retry. Every status change
+/// is flushed before the job moves on.
+pub struct Manifest {
+    _db: Db,
+    files: Tree,
+}
+
+impl Manifest {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ManifestError> {
+        let db = sled::open(path)?;
+        let files = db.open_tree(FILES_TREE)?;
+        Ok(Manifest { _db: db, files })
+    }
+
+    /// Records the files of `dir` whose name starts with one of `prefixes`
+    /// and ends in `.parquet`. A file whose contents changed after it was
+    /// processed becomes pending again, and files that disappeared from `dir`
+    /// are listed in `removed`.
+    pub fn scan(&self, dir: &Path, prefixes: &[&str]) -> Result<ScanSummary, ManifestError> {
+        let mut summary = ScanSummary::default();
+        let mut names: Vec<String> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| {
+                name.ends_with(".parquet") && prefixes.iter().any(|prefix| name.starts_with(prefix))
+            })
+            .collect();
+        names.sort();
+
+        for entry in self.entries()? {
+            if names.binary_search(&entry.file).is_err() {
+                summary.removed.push(entry.file);
+            }
+        }
+
+        for name in names {
+            let path = dir.join(&name);
+            let metadata = std::fs::metadata(&path)?;
+            let size = metadata.len();
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_millis() as u64);
+
+            match self.get(&name)? {
+                Some(entry) if entry.size == size && entry.modified == modified => {
+                    summary.unchanged += 1;
+                }
+                Some(mut entry) => {
+                    let checksum = checksum(&path)?;
+                    if checksum != entry.checksum {
+                        entry.status = FileStatus::Pending;
+                        entry.attempts = 0;
+                        entry.error = None;
+                        entry.checksum = checksum;
+                        summary.changed += 1;
+                    } else {
+                        summary.unchanged += 1;
+                    }
+                    entry.size = size;
+                    entry.modified = modified;
+                    self.put(entry)?;
+                }
+                None => {
+                    self.put(FileEntry {
+                        file: name,
+                        size,
+                        modified,
+                        checksum: checksum(&path)?,
+                        rows: None,
+                        status: FileStatus::Pending,
+                        attempts: 0,
+                        error: None,
+                        updated_at: 0,
+                    })?;
+                    summary.new += 1;
+                }
+            }
+        }
+        self.files.flush()?;
+        Ok(summary)
+    }
+
+    pub fn get(&self, file: &str) -> Result<Option<FileEntry>, ManifestError> {
+        match self.files.get(file)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every entry, in file name order.
+    pub fn entries(&self) -> Result<Vec<FileEntry>, ManifestError> {
+        self.files
+            .iter()
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    pub fn to_process(&self) -> Result<Vec<FileEntry>, ManifestError> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(FileEntry::needs_processing)
+            .collect())
+    }
+
+    pub fn start(&self, file: &str) -> Result<(), ManifestError> {
+        self.update(file, |entry| {
+            entry.status = FileStatus::Processing;
+            entry.attempts += 1;
+        })
+    }
+
+    pub fn finish(&self, file: &str, rows: u64) -> Result<(), ManifestError> {
+        self.update(file, |entry| {
+            entry.status = FileStatus::Done;
+            entry.rows = Some(rows);
+            entry.error = None;
+        })
+    }
+
+    pub fn fail(&self, file: &str, error: &str) -> Result<(), ManifestError> {
+        self.update(file, |entry| {
+            entry.status = FileStatus::Failed;
+            entry.error = Some(error.to_string());
+        })
+    }
+
+    /// Forgets a file, once whatever was written from it is gone.
+    pub fn remove(&self, file: &str) -> Result<(), ManifestError> {
+        self.files.remove(file)?;
+        self.files.flush()?;
+        Ok(())
+    }
+
+    /// Makes a file pending again with a fresh set of attempts, whatever
+    /// its status.
+    pub fn retry(&self, file: &str) -> Result<(), ManifestError> {
+        self.update(file, |entry| {
+            entry.status = FileStatus::Pending;
+            entry.attempts = 0;
+            entry.error = None;
+        })
+    }
+
+    fn update(&self, file: &str, change: impl FnOnce(&mut FileEntry)) -> Result<(), ManifestError> {
+        let mut entry = self
+            .get(file)?
+            .ok_or_else(|| ManifestError::UnknownFile(file.to_string()))?;
+        change(&mut entry);
+        self.put(entry)?;
+        self.files.flush()?;
+        Ok(())
+    }
+
+    fn put(&self, mut entry: FileEntry) -> Result<(), ManifestError> {
+        entry.updated_at = now_millis();
+        self.files
+            .insert(entry.file.as_bytes(), serde_json::to_vec(&entry)?)?;
+        Ok(())
+    }
+}
+
+fn checksum(path: &Path) -> Result<String, ManifestError> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
@@ -0,0 +1,350 @@
+use chrono::{DateTime, NaiveDateTime};
+use csv_async::StringRecord;
+use datafusion::arrow::array::{
+    ArrayRef, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder,
+    TimestampMicrosecondBuilder, UInt32Builder,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use log::warn;
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Read when `INGEST_CONFIG` does not name another file. Without it every
+/// column is inferred.
+const DEFAULT_CONFIG: &str = "ingest.yaml";
+const DEFAULT_SAMPLE_ROWS: usize = 1000;
+
+/// Columns added to every row after the ones of the file.
+pub const UUID_COLUMN: &str = "_uuid";
+pub const FILE_ID_COLUMN: &str = "_file_id";
+
+/// Timestamp layouts recognised besides RFC 3339, read as UTC.
+const TIMESTAMP_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%d/%m/%Y %H:%M:%S%.f",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Int,
+    Float,
+    Timestamp,
+    Bool,
+    String,
+}
+
+/// Tried in this order; a column takes the first type every sampled value
+/// parses as, and falls back to `String`.
+const INFERENCE_ORDER: [ColumnType; 4] = [
+    ColumnType::Int,
+    ColumnType::Float,
+    ColumnType::Timestamp,
+    ColumnType::Bool,
+];
+
+impl ColumnType {
+    pub fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Int => DataType::Int64,
+            ColumnType::Float => DataType::Float64,
+            ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+            ColumnType::Bool => DataType::Boolean,
+            ColumnType::String => DataType::Utf8,
+        }
+    }
+
+    fn parse(self, value: &str) -> Option<Value> {
+        match self {
+            ColumnType::Int => value.parse().ok().map(Value::Int),
+            ColumnType::Float => value.parse().ok().map(Value::Float),
+            ColumnType::Timestamp => parse_timestamp(value).map(Value::Timestamp),
+            ColumnType::Bool => match value.to_ascii_lowercase().as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            ColumnType::String => Some(Value::String(value.to_string())),
+        }
+    }
+}
+
+/// Microseconds since the epoch.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.timestamp_micros());
+    }
+    TIMESTAMP_FORMATS.iter().find_map(|format| {
+        NaiveDateTime::parse_from_str(value, format)
+            .ok()
+            .map(|timestamp| timestamp.and_utc().timestamp_micros())
+    })
+}
+
+enum Value {
+    Null,
+    Int(i64),
+    Float(f64),
+    Timestamp(i64),
+    Bool(bool),
+    String(String),
+}
+
+/// `ingest.yaml`: how many rows to sample and the types that should not be
+/// inferred, e.g. phone numbers that look like integers but must keep their
+/// leading zeros.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestConfig {
+    #[serde(default = "default_sample_rows")]
+    pub sample_rows: usize,
+    #[serde(default)]
+    pub columns: BTreeMap<String, ColumnType>,
+}
+
+fn default_sample_rows() -> usize {
+    DEFAULT_SAMPLE_ROWS
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        IngestConfig {
+            sample_rows: DEFAULT_SAMPLE_ROWS,
+            columns: BTreeMap::new(),
+        }
+    }
+}
+
+impl IngestConfig {
+    /// The file named by `INGEST_CONFIG`, else `ingest.yaml` if there is
+    /// one, else the defaults.
+    pub fn configured() -> Result<Self, Box<dyn Error>> {
+        match std::env::var("INGEST_CONFIG") {
+            Ok(path) => Self::load(path),
+            Err(_) if Path::new(DEFAULT_CONFIG).exists() => Self::load(DEFAULT_CONFIG),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let config: IngestConfig = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+        if config.sample_rows == 0 {
+            return Err("sample_rows must be at least 1".into());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+/// A row that could not be converted to the schema.
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    /// Position of the row in the chunk.
+    pub row: usize,
+    pub reason: String,
+}
+
+/// The typed schema of a CSV file, in the order of its header, followed by
+/// `_uuid` and `_file_id`.
+#[derive(Debug, Clone)]
+pub struct CsvSchema {
+    columns: Vec<Column>,
+    schema: SchemaRef,
+}
+
+impl CsvSchema {
+    /// Infers every column from `sample` unless `config` gives its type.
+    /// Empty values are nulls: a typed column is always nullable, since rows
+    /// past the sample may leave it empty, while a string column keeps empty
+    /// strings and is never null.
+    pub fn infer(headers: &StringRecord, sample: &[StringRecord], config: &IngestConfig) -> Self {
+        for name in config.columns.keys() {
+            if !headers.iter().any(|header| header == name) {
+                warn!("Type given for {} but the file has no such column", name);
+            }
+        }
+
+        let columns: Vec<Column> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let present: Vec<&str> = sample
+                    .iter()
+                    .filter_map(|record| record.get(i))
+                    .filter(|value| !value.is_empty())
+                    .collect();
+                let column_type = config.columns.get(name).copied().unwrap_or_else(|| {
+                    INFERENCE_ORDER
+                        .into_iter()
+                        .find(|column_type| {
+                            !present.is_empty()
+                                && present
+                                    .iter()
+                                    .all(|value| column_type.parse(value).is_some())
+                        })
+                        .unwrap_or(ColumnType::String)
+                });
+                let nullable = column_type != ColumnType::String;
+                Column {
+                    name: name.to_string(),
+                    column_type,
+                    nullable,
+                }
+            })
+            .collect();
+
+        let mut fields: Vec<Field> = columns
+            .iter()
+            .map(|column| {
+                Field::new(
+                    &column.name,
+                    column.column_type.data_type(),
+                    column.nullable,
+                )
+            })
+            .collect();
+        fields.push(Field::new(UUID_COLUMN, DataType::Utf8, false));
+        fields.push(Field::new(FILE_ID_COLUMN, DataType::UInt32, false));
+
+        CsvSchema {
+            columns,
+            schema: Arc::new(Schema::new(fields)),
+        }
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Converts the rows that match the schema into a batch and returns the
+    /// others with the reason they were rejected.
+    pub fn to_batch(
+        &self,
+        rows: &[StringRecord],
+        file_id: u32,
+    ) -> Result<(RecordBatch, Vec<Rejected>), ArrowError> {
+        let mut builders: Vec<ColumnBuilder> = self
+            .columns
+            .iter()
+            .map(|column| ColumnBuilder::new(column.column_type, rows.len()))
+            .collect();
+        let mut uuids = StringBuilder::with_capacity(rows.len(), rows.len() * 36);
+        let mut file_ids = UInt32Builder::with_capacity(rows.len());
+        let mut rejected = Vec::new();
+
+        for (i, record) in rows.iter().enumerate() {
+            match self.parse_row(record) {
+                Ok(values) => {
+                    for (builder, value) in builders.iter_mut().zip(values) {
+                        builder.append(value);
+                    }
+                    uuids.append_value(Uuid::new_v4().to_string());
+                    file_ids.append_value(file_id);
+                }
+                Err(reason) => rejected.push(Rejected { row: i, reason }),
+            }
+        }
+
+        let mut arrays: Vec<ArrayRef> = builders.into_iter().map(ColumnBuilder::finish).collect();
+        arrays.push(Arc::new(uuids.finish()));
+        arrays.push(Arc::new(file_ids.finish()));
+        let batch = RecordBatch::try_new(self.schema(), arrays)?;
+        Ok((batch, rejected))
+    }
+
+    fn parse_row(&self, record: &StringRecord) -> Result<Vec<Value>, String> {
+        if record.len() != self.columns.len() {
+            return Err(format!(
+                "expected {} fields, found {}",
+                self.columns.len(),
+                record.len()
+            ));
+        }
+        self.columns
+            .iter()
+            .zip(record.iter())
+            .map(|(column, value)| match column.column_type {
+                ColumnType::String => Ok(Value::String(value.to_string())),
+                _ if value.is_empty() => Ok(Value::Null),
+                column_type => column_type.parse(value).ok_or_else(|| {
+                    format!(
+                        "{}: {:?} is not a valid {:?}",
+                        column.name, value, column_type
+                    )
+                }),
+            })
+            .collect()
+    }
+}
+
+enum ColumnBuilder {
+    Int(Int64Builder),
+    Float(Float64Builder),
+    Timestamp(TimestampMicrosecondBuilder),
+    Bool(BooleanBuilder),
+    String(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(column_type: ColumnType, capacity: usize) -> Self {
+        match column_type {
+            ColumnType::Int => ColumnBuilder::Int(Int64Builder::with_capacity(capacity)),
+            ColumnType::Float => ColumnBuilder::Float(Float64Builder::with_capacity(capacity)),
+            ColumnType::Timestamp => {
+                ColumnBuilder::Timestamp(TimestampMicrosecondBuilder::with_capacity(capacity))
+            }
+            ColumnType::Bool => ColumnBuilder::Bool(BooleanBuilder::with_capacity(capacity)),
+            ColumnType::String => {
+                ColumnBuilder::String(StringBuilder::with_capacity(capacity, capacity * 16))
+            }
+        }
+    }
+
+    /// `value` was parsed for this builder's type, so other variants do not
+    /// occur.
+    fn append(&mut self, value: Value) {
+        match (self, value) {
+            (ColumnBuilder::Int(builder), Value::Int(value)) => builder.append_value(value),
+            (ColumnBuilder::Float(builder), Value::Float(value)) => builder.append_value(value),
+            (ColumnBuilder::Timestamp(builder), Value::Timestamp(value)) => {
+                builder.append_value(value)
+            }
+            (ColumnBuilder::Bool(builder), Value::Bool(value)) => builder.append_value(value),
+            (ColumnBuilder::String(builder), Value::String(value)) => builder.append_value(value),
+            (ColumnBuilder::Int(builder), _) => builder.append_null(),
+            (ColumnBuilder::Float(builder), _) => builder.append_null(),
+            (ColumnBuilder::Timestamp(builder), _) => builder.append_null(),
+            (ColumnBuilder::Bool(builder), _) => builder.append_null(),
+            (ColumnBuilder::String(builder), _) => builder.append_null(),
+        }
+    }
+
+    fn finish(self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Timestamp(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Bool(mut builder) => Arc::new(builder.finish()),
+            ColumnBuilder::String(mut builder) => Arc::new(builder.finish()),
+        }
+    }
+}
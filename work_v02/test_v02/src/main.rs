@@ -1,17 +1,10 @@
-use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Arc;
-use uuid::Uuid;
-
-use csv_async::{AsyncReaderBuilder, Trim};
-use datafusion::{
-    arrow::{array::{StringArray, ArrayRef}, datatypes::{DataType, Field, Schema}, record_batch::RecordBatch},
-    dataframe::DataFrameWriteOptions,
-    execution::context::SessionContext,
-    prelude::*,
-};
+
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use csv_schema::{CsvSchema, IngestConfig};
+use datafusion::parquet::arrow::AsyncArrowWriter;
 use futures::stream::StreamExt;
-use log::{error, info};
+use log::{error, info, warn};
 use tokio::fs::File;
 use tokio::io::BufReader;
 
@@ -28,82 +21,96 @@ use std::hash::{Hash, Hasher};
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
-    let file_name = "cdr.csv";
-    let file = File::open(file_name).await?;
+    let file_name = std::env::args().nth(1).unwrap_or_else(|| "cdr.csv".to_string());
+    let config = IngestConfig::configured()?;
+    let file = File::open(&file_name).await?;
     let reader = BufReader::new(file);
-    let file_id = hash_filename(file_name);
+    let file_id = hash_filename(&file_name);
     let mut csv_reader = AsyncReaderBuilder::new()
         .has_headers(true)
         .trim(Trim::All)
-        .create_deserializer(reader);
+        .create_reader(reader);
+
+    // Records keep the header's column order, unlike a HashMap per row
+    let headers = csv_reader.headers().await?.clone();
+    let mut records_stream = csv_reader.records();
+
+    // Infer the column types from the first rows of the file
+    let mut sample = Vec::with_capacity(config.sample_rows);
+    while sample.len() < config.sample_rows {
+        match records_stream.next().await {
+            Some(Ok(record)) => sample.push(record),
+            Some(Err(err)) => error!("Error reading record: {}", err),
+            None => break,
+        }
+    }
+    let schema = CsvSchema::infer(&headers, &sample, &config);
+    for column in schema.columns() {
+        info!(
+            "Column {}: {:?}{}",
+            column.name,
+            column.column_type,
+            if column.nullable { ", nullable" } else { "" }
+        );
+    }
 
-    let mut records_stream = csv_reader.deserialize::<HashMap<String, String>>();
+    // Every chunk is appended to one writer; writing each chunk to the path
+    // on its own kept only the last one
+    let target_path = "data.parquet";
+    let target = File::create(target_path).await?;
+    let mut writer = AsyncArrowWriter::try_new(target, schema.schema(), None)?;
+    let mut rows = 0;
 
     let chunk_size = 10;
     let mut chunk = Vec::with_capacity(chunk_size);
 
-    while let Some(record) = records_stream.next().await {
-        match record {
-            Ok(mut record) => {
-                // Generate a UUID and add it to the record
-                record.insert("_uuid".to_string(), Uuid::new_v4().to_string());
-                record.insert("_file_id".to_string(), file_id.to_string());
-                chunk.push(record);
-            }
-            Err(err) => error!("Error deserializing record: {}", err),
-        }
+    let mut sample = sample.into_iter();
+    loop {
+        let record = match sample.next() {
+            Some(record) => record,
+            None => match records_stream.next().await {
+                Some(Ok(record)) => record,
+                Some(Err(err)) => {
+                    error!("Error reading record: {}", err);
+                    continue;
+                }
+                None => break,
+            },
+        };
+        chunk.push(record);
 
         if chunk.len() == chunk_size {
             info!("Processing a chunk of {} records", chunk.len());
-            process_chunk(&chunk).await?;
+            rows += process_chunk(&mut writer, &schema, file_id, &chunk).await?;
             chunk.clear();
         }
     }
 
     if !chunk.is_empty() {
         info!("Processing remaining records");
-        process_chunk(&chunk).await?;
+        rows += process_chunk(&mut writer, &schema, file_id, &chunk).await?;
     }
 
+    writer.close().await?;
+    info!("Wrote {} records to {}", rows, target_path);
     Ok(())
 }
 
-async fn process_chunk(chunk: &[HashMap<String, String>]) -> Result<(), Box<dyn Error>> {
-    let mut columns: HashMap<String, Vec<String>> = HashMap::new();
-
-    for record in chunk {
-        for (key, value) in record {
-            columns.entry(key.clone()).or_default().push(value.clone());
-        }
-    }
-
-    let mut string_arrays: Vec<ArrayRef> = Vec::new();
-    let mut fields = Vec::new();
-
-    for (column_name, values) in &columns {
-        fields.push(Field::new(column_name, DataType::Utf8, false));
-        string_arrays.push(Arc::new(StringArray::from(values.clone())) as ArrayRef);
+async fn process_chunk(
+    writer: &mut AsyncArrowWriter<File>,
+    schema: &CsvSchema,
+    file_id: u32,
+    chunk: &[StringRecord],
+) -> Result<usize, Box<dyn Error>> {
+    let (batch, rejected) = schema.to_batch(chunk, file_id)?;
+    for row in &rejected {
+        let line = chunk[row.row]
+            .position()
+            .map_or(0, |position| position.line());
+        warn!("Rejected record on line {}: {}", line, row.reason);
     }
 
-    let schema = Arc::new(Schema::new(fields));
-    let batch = RecordBatch::try_new(schema.clone(), string_arrays)?;
-
-    info!("Schema:\n{:?}", schema);
-
-    let table = datafusion::datasource::memory::MemTable::try_new(schema, vec![vec![batch]])?;
-
-    let ctx = SessionContext::new();
-    ctx.register_table("my_table", Arc::new(table))?;
+    writer.write(&batch).await?;
 
-    let df = ctx.sql("SELECT * FROM my_table").await?;
-    df.clone().show().await?;
-
-    let target_path = "data.parquet";
-    df.write_parquet(
-        target_path,
-        DataFrameWriteOptions::new(),
-        None,
-    ).await?;
-
-    Ok(())
+    Ok(batch.num_rows())
 }
@@ -1,18 +1,26 @@
-use csv::ReaderBuilder;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use csv_schema::{CsvSchema, IngestConfig};
+use datafusion::arrow::error::ArrowError;
+use datafusion::parquet::arrow::AsyncArrowWriter;
+use datafusion::parquet::errors::ParquetError;
+use futures::stream::{self, Stream, StreamExt};
+use log::{error, info, warn};
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufReader};
-use uuid::Uuid;
+use tokio::io::BufReader;
 
-const DEFAULT_CHUNK_SIZE: usize = 10;
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024; // rows per row group
 const DEFAULT_BUFFER_SIZE: usize = 256 * 1024; // 256K
 
 struct ReadOptions {
+    /// Rows held in memory at a time; each chunk becomes a row group.
     chunk_size: usize,
+    /// Bytes read from the file at a time.
     buffer_size: usize,
+    config: IngestConfig,
 }
 
 impl ReadOptions {
@@ -20,18 +28,92 @@ impl ReadOptions {
         ReadOptions {
             chunk_size: DEFAULT_CHUNK_SIZE,
             buffer_size: DEFAULT_BUFFER_SIZE,
+            config: IngestConfig::default(),
         }
     }
 
     fn with_chunk_size(mut self, chunk_size: usize) -> Self {
-        self.chunk_size = chunk_size;
+        self.chunk_size = chunk_size.max(1);
         self
     }
 
     fn with_buffer_size(mut self, buffer_size: usize) -> Self {
-        self.buffer_size = buffer_size;
+        self.buffer_size = buffer_size.max(1);
         self
     }
+
+    fn with_config(mut self, config: IngestConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+#[derive(Debug)]
+enum SourceError {
+    Io(std::io::Error),
+    Csv(csv_async::Error),
+    Arrow(ArrowError),
+    Parquet(ParquetError),
+}
+
+impl std::error::Error for SourceError {}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Io(e) => write!(f, "IO error: {}", e),
+            SourceError::Csv(e) => write!(f, "CSV error: {}", e),
+            SourceError::Arrow(e) => write!(f, "Arrow error: {}", e),
+            SourceError::Parquet(e) => write!(f, "Parquet error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for SourceError {
+    fn from(e: std::io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+impl From<csv_async::Error> for SourceError {
+    fn from(e: csv_async::Error) -> Self {
+        SourceError::Csv(e)
+    }
+}
+
+impl From<ArrowError> for SourceError {
+    fn from(e: ArrowError) -> Self {
+        SourceError::Arrow(e)
+    }
+}
+
+impl From<ParquetError> for SourceError {
+    fn from(e: ParquetError) -> Self {
+        SourceError::Parquet(e)
+    }
+}
+
+/// What a `FileSource::read` did.
+#[derive(Debug, Default, Clone, Copy)]
+struct ReadSummary {
+    /// Records in the file, rejected ones included.
+    rows_read: u64,
+    /// Records that could not be parsed or did not match the schema.
+    rows_rejected: u64,
+    row_groups: usize,
+    bytes_written: u64,
+}
+
+impl ReadSummary {
+    fn rows_written(&self) -> u64 {
+        self.rows_read - self.rows_rejected
+    }
+
+    fn reject(&mut self, err: csv_async::Error) {
+        error!("Error reading record: {}", err);
+        self.rows_read += 1;
+        self.rows_rejected += 1;
+    }
 }
 
 struct FileSource {
@@ -47,8 +129,13 @@ impl FileSource {
                 format!("File '{}' not found", path),
             ));
         }
-        let filename = Self::get_filename(&path);
-        let file_id = Self::hash_filename(&filename.unwrap());
+        let filename = Self::get_filename(&path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a file name", path),
+            )
+        })?;
+        let file_id = Self::hash_filename(filename);
         Ok(FileSource { file_id, path })
     }
 
@@ -57,9 +144,7 @@ impl FileSource {
     }
 
     async fn exists(path: &str) -> bool {
-        tokio::task::spawn_blocking(move || Path::new(path).exists())
-            .await
-            .unwrap()
+        tokio::fs::try_exists(path).await.unwrap_or(false)
     }
 
     fn hash_filename(filename: &str) -> u32 {
@@ -68,58 +153,162 @@ impl FileSource {
         hasher.finish() as u32
     }
 
-    async fn read(&self, options: ReadOptions) -> Result<String, std::io::Error> {
+    /// The file next to the CSV with a `.parquet` extension.
+    fn parquet_path(&self) -> PathBuf {
+        Path::new(&self.path).with_extension("parquet")
+    }
+
+    /// Streams the CSV into `output` one chunk at a time, so the file can be
+    /// of any size. Columns are typed from the first `sample_rows` records
+    /// and records that do not fit are logged and skipped.
+    ///
+    /// The Parquet file is written under a temporary name and renamed when
+    /// complete, so a failed read leaves no partial file behind.
+    async fn read(&self, options: &ReadOptions, output: &Path) -> Result<ReadSummary, SourceError> {
         let file = File::open(&self.path).await?;
-        let reader = BufReader::new(file);
-        let mut csv_reader = ReaderBuilder::new()
+        let reader = BufReader::with_capacity(options.buffer_size, file);
+        let mut csv_reader = AsyncReaderBuilder::new()
             .has_headers(true)
-            .trim(true)
-            .from_reader(reader);
-        let mut records_stream = csv_reader.deserialize::<HashMap<String, String>>();
+            .trim(Trim::All)
+            .create_reader(reader);
+        let headers = csv_reader.headers().await?.clone();
+        let mut records = csv_reader.records();
+        let mut summary = ReadSummary::default();
+
+        let mut sample = Vec::with_capacity(options.config.sample_rows);
+        while sample.len() < options.config.sample_rows {
+            match records.next().await {
+                Some(Ok(record)) => sample.push(record),
+                Some(Err(err)) => summary.reject(err),
+                None => break,
+            }
+        }
+        let schema = CsvSchema::infer(&headers, &sample, &options.config);
+        for column in schema.columns() {
+            info!(
+                "Column {}: {:?}{}",
+                column.name,
+                column.column_type,
+                if column.nullable { ", nullable" } else { "" }
+            );
+        }
 
-        let chunk_size = ReadOptions.chunk_size;
+        let partial = output.with_extension("parquet.partial");
+        let records = stream::iter(sample.into_iter().map(Ok)).chain(records);
+        let written = self
+            .write(&schema, records, options.chunk_size, &partial, &mut summary)
+            .await;
+        match written {
+            Ok(()) => tokio::fs::rename(&partial, output).await?,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        }
+        summary.bytes_written = tokio::fs::metadata(output).await?.len();
+        Ok(summary)
+    }
+
+    async fn write<S>(
+        &self,
+        schema: &CsvSchema,
+        records: S,
+        chunk_size: usize,
+        path: &Path,
+        summary: &mut ReadSummary,
+    ) -> Result<(), SourceError>
+    where
+        S: Stream<Item = Result<StringRecord, csv_async::Error>>,
+    {
+        futures::pin_mut!(records);
+        let mut writer =
+            AsyncArrowWriter::try_new(File::create(path).await?, schema.schema(), None)?;
         let mut chunk = Vec::with_capacity(chunk_size);
 
-        while let Some(record) = records_stream.next().await {
+        while let Some(record) = records.next().await {
             match record {
-                Ok(mut record) => {
-                    // Generate a UUID and add it to the record
-                    record.insert("_uuid".to_string(), Uuid::new_v4().to_string());
-                    record.insert("_file_id".to_string(), file_id.to_string());
-                    chunk.push(record);
-                }
-                Err(err) => error!("Error deserializing record: {}", err),
+                Ok(record) => chunk.push(record),
+                Err(err) => summary.reject(err),
             }
 
             if chunk.len() == chunk_size {
-                process_chunk(&chunk).await?;
+                self.write_chunk(&mut writer, schema, &chunk, summary)
+                    .await?;
                 chunk.clear();
             }
         }
 
         if !chunk.is_empty() {
-            process_chunk(&chunk).await?;
+            self.write_chunk(&mut writer, schema, &chunk, summary)
+                .await?;
         }
 
+        writer.close().await?;
+        Ok(())
+    }
+
+    /// Writes the valid records of `chunk` as one row group.
+    async fn write_chunk(
+        &self,
+        writer: &mut AsyncArrowWriter<File>,
+        schema: &CsvSchema,
+        chunk: &[StringRecord],
+        summary: &mut ReadSummary,
+    ) -> Result<(), SourceError> {
+        let (batch, rejected) = schema.to_batch(chunk, self.file_id)?;
+        for row in &rejected {
+            let line = chunk[row.row]
+                .position()
+                .map_or(0, |position| position.line());
+            warn!("Rejected record on line {}: {}", line, row.reason);
+        }
+        summary.rows_read += chunk.len() as u64;
+        summary.rows_rejected += rejected.len() as u64;
+
+        if batch.num_rows() > 0 {
+            writer.write(&batch).await?;
+            writer.flush().await?;
+            summary.row_groups += 1;
+        }
         Ok(())
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let file_path = "example.csv";
-    let options = ReadOptions::new().with_chunk_size(100);
+    env_logger::init();
+    let file_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "example.csv".to_string());
+    let config = match IngestConfig::configured() {
+        Ok(config) => config,
+        Err(error) => {
+            println!("Error: {}", error);
+            return;
+        }
+    };
+    let options = ReadOptions::new()
+        .with_chunk_size(DEFAULT_CHUNK_SIZE)
+        .with_buffer_size(DEFAULT_BUFFER_SIZE)
+        .with_config(config);
 
-    match FileSource::new(file_path.to_string()).await {
+    match FileSource::new(file_path).await {
         Ok(file_source) => {
-            println!("File '{}' found", file_source.path);
-            match file_source.read(options).await {
-                Ok(content) => println!("File content: {}", content),
+            let output = file_source.parquet_path();
+            match file_source.read(&options, &output).await {
+                Ok(summary) => println!(
+                    "{}: {} rows read, {} rejected, {} written to {} ({} row groups, {} bytes)",
+                    file_source.path,
+                    summary.rows_read,
+                    summary.rows_rejected,
+                    summary.rows_written(),
+                    output.display(),
+                    summary.row_groups,
+                    summary.bytes_written
+                ),
                 Err(error) => println!("Error reading file: {}", error),
             }
         }
         Err(error) => println!("Error: {}", error),
     }
-
-    println!("File exists: {}", FileSource::exists(file_path).await);
 }